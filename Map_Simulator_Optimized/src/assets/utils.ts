@@ -1,16 +1,21 @@
 import { World } from "rust_simulator";
 import type { SettingsValue } from "../types/types";
 
-export const drawOwnershipLayer = (
+// Copies a layer buffer onto its canvas. `rect` is what the render_* call returned,
+// [x, y, width, height] or empty when nothing changed, so only that part is uploaded.
+// `full` uploads the whole buffer, for a canvas that was not drawn from this world yet.
+export const putLayer = (
     ctx: CanvasRenderingContext2D,
     world: World,
-    memory: WebAssembly.Memory
+    memory: WebAssembly.Memory,
+    ptr: number,
+    rect: Uint32Array,
+    full: boolean
 ) => {
-    world.render_ownership();
+    if (!full && rect.length < 4) return;
 
     const width = world.width();
     const height = world.height();
-    const ptr = world.get_ownership_buffer_ptr();
 
     const data = new Uint8ClampedArray(
         memory.buffer,
@@ -19,28 +24,31 @@ export const drawOwnershipLayer = (
     );
 
     const imageData = new ImageData(data, width, height);
-    ctx.putImageData(imageData, 0, 0);
+    if (full) {
+        ctx.putImageData(imageData, 0, 0);
+    } else {
+        ctx.putImageData(imageData, 0, 0, rect[0], rect[1], rect[2], rect[3]);
+    }
 };
 
-export const drawTerrainLayer = (
+export const drawOwnershipLayer = (
     ctx: CanvasRenderingContext2D,
     world: World,
-    memory: WebAssembly.Memory
+    memory: WebAssembly.Memory,
+    full: boolean = false
 ) => {
-    world.render_terrain();
-
-    const width = world.width();
-    const height = world.height();
-    const ptr = world.get_terrain_buffer_ptr();
-
-    const data = new Uint8ClampedArray(
-        memory.buffer,
-        ptr,
-        width * height * 4
-    );
+    const rect = world.render_ownership();
+    putLayer(ctx, world, memory, world.get_ownership_buffer_ptr(), rect, full);
+};
 
-    const imageData = new ImageData(data, width, height);
-    ctx.putImageData(imageData, 0, 0);
+export const drawTerrainLayer = (
+    ctx: CanvasRenderingContext2D,
+    world: World,
+    memory: WebAssembly.Memory,
+    full: boolean = false
+) => {
+    const rect = world.render_terrain();
+    putLayer(ctx, world, memory, world.get_terrain_buffer_ptr(), rect, full);
 };
 
 // Helper to draw the Distance Buffer
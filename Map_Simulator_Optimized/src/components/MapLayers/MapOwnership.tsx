@@ -3,10 +3,14 @@ import React, { useRef, useEffect } from "react";
 import { useSettingsController, useSettingsSelector } from "../../context/Context";
 
 import { drawOwnershipLayer, formatSettings, hexToColorInt } from "../../assets/utils";
+import type { World } from "rust_simulator";
 
 
 function MapOwnership({mode} : {mode: "SIMULATION" | "EDITOR"}) {
     const canvasRef = useRef<HTMLCanvasElement | null>(null);
+    // world this canvas was last drawn from, and whether capital markers are on it
+    const drawnWorld = useRef<World | null>(null);
+    const markersDrawn = useRef(false);
 
     const controller = useSettingsController();
     const world = controller.world;
@@ -24,7 +28,10 @@ function MapOwnership({mode} : {mode: "SIMULATION" | "EDITOR"}) {
         if (!world || !memory || !ctx || !canvas) return;
 
 
-        drawOwnershipLayer(ctx, world, memory);
+        // markers are drawn over the buffer, so with them the whole canvas is uploaded again
+        drawOwnershipLayer(ctx, world, memory, drawnWorld.current !== world || markersDrawn.current || showEMpires);
+        drawnWorld.current = world;
+        markersDrawn.current = showEMpires;
 
         if ( !showEMpires) return; 
 
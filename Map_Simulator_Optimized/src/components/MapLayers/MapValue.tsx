@@ -1,11 +1,15 @@
 import React, { useRef, useEffect } from "react";
 
 import { useSettingsController, useSettingsSelector } from "../../context/Context";
+import { putLayer } from "../../assets/utils";
+import type { World } from "rust_simulator";
 
 
 
 function MapValue() {
     const canvasRef = useRef<HTMLCanvasElement | null>(null);
+    // world this canvas was last drawn from, a new one needs a full upload
+    const drawnWorld = useRef<World | null>(null);
 
     const controller = useSettingsController();
     const world = controller.world;
@@ -33,23 +37,9 @@ function MapValue() {
         }
 
 
-        /////cahnge to render_resource after modifying rust
-        world.render_resources(); 
-
-        const width = world.width();
-        const height = world.height();
-
-        const ptr = world.get_resource_buffer_ptr();
-
-        const data = new Uint8ClampedArray(
-            memory.buffer, 
-            ptr,
-            width * height * 4
-        );
-
-        const imageData = new ImageData(data, width, height);
-        
-        ctx.putImageData(imageData, 0, 0);
+        const rect = world.render_resources();
+        putLayer(ctx, world, memory, world.get_resource_buffer_ptr(), rect, drawnWorld.current !== world);
+        drawnWorld.current = world;
 
     }, [world, resourceVersion]); 
 
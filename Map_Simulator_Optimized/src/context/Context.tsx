@@ -195,7 +195,7 @@ class SettingsStore{
 
     async exportTerrainFile(){
         if ( !this.world ) return;
-        const mapData = this.world.export_map_to_string();
+        const mapData = this.world.export_map_to_string(true);
 
         try{
             const blob = new Blob([mapData], { type: 'text/plain'});
@@ -220,7 +220,7 @@ class SettingsStore{
 
     async exportResourceFile(){
         if ( !this.world ) return;
-        const mapData = this.world.export_resource_to_string();
+        const mapData = this.world.export_resource_to_string(true);
 
         try{
             const blob = new Blob([mapData], { type: 'text/plain'});
@@ -0,0 +1,84 @@
+// Compares the sequential and parallel auto_grow on the maps bundled with the frontend,
+// with many empires spread out. Run with `cargo bench --bench growth`, the parallel
+// search needs several cores to come out ahead.
+
+use std::time::{Duration, Instant};
+
+use rust_simulator::World;
+
+const MAPS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../Map_Simulator_Optimized/public/resources/maps");
+const SETTINGS: [u32; 8] = [9999, 25, 10, 15, 80, 60, 20, 100];
+const RUNS: usize = 3;
+const EMPIRES: usize = 30;
+const GROW_TICKS: usize = 10;
+
+// Spread capitals over the map on plain tiles, same spots for both runs
+fn capitals(map: &str) -> Vec<(usize, usize)> {
+    let rows: Vec<&[u8]> = map.lines().filter(|l| !l.is_empty()).map(|l| l.trim().as_bytes()).collect();
+    let mut found = Vec::new();
+    let step = (rows.len() * rows[0].len() / (EMPIRES * 4)).max(1);
+
+    let mut index = step / 2;
+    while found.len() < EMPIRES && index < rows.len() * rows[0].len() {
+        let (x, y) = (index % rows[0].len(), index / rows[0].len());
+        if rows[y][x] == b'P' {
+            found.push((x, y));
+        }
+        index += step;
+    }
+    found
+}
+
+fn buffer(ptr: *const u32, len: usize) -> Vec<u32> {
+    unsafe { std::slice::from_raw_parts(ptr, len).to_vec() }
+}
+
+// Time spent in auto_grow and the territories it left
+fn run(map: &str, parallel: bool) -> (Duration, Vec<u32>) {
+    let mut world = World::new(map, None);
+    world.set_parallel_growth(parallel);
+    for (i, (x, y)) in capitals(map).into_iter().enumerate() {
+        world.add_empire(x, y, i as u32 + 1, 0xFF000000 | (i as u32 * 0x1F3D5B), 20, SETTINGS.to_vec());
+    }
+
+    let timer = Instant::now();
+    for tick in 0..GROW_TICKS {
+        world.auto_grow(200 + 100 * tick as u32, true);
+    }
+    let grow = timer.elapsed();
+
+    world.render_ownership();
+    (grow, buffer(world.get_ownership_buffer_ptr(), world.width() * world.height()))
+}
+
+fn main() {
+    let mut maps: Vec<_> = std::fs::read_dir(MAPS_DIR)
+        .expect("bundled maps not found")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    maps.sort();
+
+    println!("{} threads", rayon::current_num_threads());
+    println!("{:<22} {:>9} | {:>10} {:>10} {:>6}", "map", "tiles", "sequential", "parallel", "x");
+
+    for path in maps {
+        let map = std::fs::read_to_string(&path).unwrap();
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+
+        let mut totals = [Duration::ZERO; 2];
+        for _ in 0..RUNS {
+            let (sequential, sequential_pixels) = run(&map, false);
+            let (parallel, parallel_pixels) = run(&map, true);
+            assert!(sequential_pixels == parallel_pixels, "{}: territories differ", name);
+
+            totals[0] += sequential;
+            totals[1] += parallel;
+        }
+
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0 / RUNS as f64;
+        let tiles = map.lines().filter(|l| !l.is_empty()).map(|l| l.trim().len()).sum::<usize>();
+        println!("{:<22} {:>9} | {:>8.2}ms {:>8.2}ms {:>5.2}x",
+            name, tiles, ms(totals[0]), ms(totals[1]), ms(totals[0]) / ms(totals[1]));
+    }
+}
@@ -0,0 +1,108 @@
+// Compares the BinaryHeap and bucket queue backends of the Dijkstra searches
+// on the maps bundled with the frontend. Run with `cargo bench --bench queue`.
+
+use std::time::{Duration, Instant};
+
+use rust_simulator::{QueueBackend, World};
+
+const MAPS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../Map_Simulator_Optimized/public/resources/maps");
+const SETTINGS: [u32; 8] = [9999, 25, 10, 15, 80, 60, 20, 100];
+const RUNS: usize = 5;
+const EMPIRES: usize = 8;
+const GROW_TICKS: usize = 10;
+
+// Spread capitals over the map on plain tiles, same spots for both backends
+fn capitals(map: &str) -> Vec<(usize, usize)> {
+    let rows: Vec<&[u8]> = map.lines().filter(|l| !l.is_empty()).map(|l| l.trim().as_bytes()).collect();
+    let mut found = Vec::new();
+    let step = (rows.len() * rows[0].len() / (EMPIRES * 4)).max(1);
+
+    let mut index = step / 2;
+    while found.len() < EMPIRES && index < rows.len() * rows[0].len() {
+        let (x, y) = (index % rows[0].len(), index / rows[0].len());
+        if rows[y][x] == b'P' {
+            found.push((x, y));
+        }
+        index += step;
+    }
+    found
+}
+
+fn buffer(ptr: *const u32, len: usize) -> Vec<u32> {
+    unsafe { std::slice::from_raw_parts(ptr, len).to_vec() }
+}
+
+struct Outcome {
+    dist: Duration,
+    grow: Duration,
+    dist_pixels: Vec<u32>,
+    owner_pixels: Vec<u32>,
+}
+
+fn run(map: &str, backend: QueueBackend) -> Outcome {
+    let mut world = World::new(map, None);
+    world.set_queue_backend(backend);
+    let size = world.width() * world.height();
+    let starts = capitals(map);
+
+    let timer = Instant::now();
+    let (x, y) = starts[0];
+    world.djisktra_dist_point(x, y, 0, SETTINGS.to_vec());
+    let dist = timer.elapsed();
+    world.render_dist_map(None);
+
+    let timer = Instant::now();
+    for (i, &(x, y)) in starts.iter().enumerate() {
+        world.add_empire(x, y, i as u32 + 1, 0xFF000000 | (i as u32 * 0x1F3D5B), 20, SETTINGS.to_vec());
+    }
+    for _ in 0..GROW_TICKS {
+        world.auto_grow(500, true);
+    }
+    let grow = timer.elapsed();
+    world.render_ownership();
+
+    Outcome {
+        dist,
+        grow,
+        dist_pixels: buffer(world.get_dist_buffer_ptr(), size),
+        owner_pixels: buffer(world.get_ownership_buffer_ptr(), size),
+    }
+}
+
+fn main() {
+    let mut maps: Vec<_> = std::fs::read_dir(MAPS_DIR)
+        .expect("bundled maps not found")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    maps.sort();
+
+    println!("{:<22} {:>9} | {:>10} {:>10} {:>6} | {:>10} {:>10} {:>6}",
+        "map", "tiles", "dist heap", "dist bkt", "x", "grow heap", "grow bkt", "x");
+
+    for path in maps {
+        let map = std::fs::read_to_string(&path).unwrap();
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+
+        let mut totals = [[Duration::ZERO; 2]; 2];
+        for _ in 0..RUNS {
+            let heap = run(&map, QueueBackend::BinaryHeap);
+            let bucket = run(&map, QueueBackend::Bucket);
+
+            assert!(heap.dist_pixels == bucket.dist_pixels, "{}: distance maps differ", name);
+            assert!(heap.owner_pixels == bucket.owner_pixels, "{}: territories differ", name);
+
+            totals[0][0] += heap.dist;
+            totals[0][1] += bucket.dist;
+            totals[1][0] += heap.grow;
+            totals[1][1] += bucket.grow;
+        }
+
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0 / RUNS as f64;
+        let tiles = map.lines().filter(|l| !l.is_empty()).map(|l| l.trim().len()).sum::<usize>();
+        println!("{:<22} {:>9} | {:>8.2}ms {:>8.2}ms {:>5.2}x | {:>8.2}ms {:>8.2}ms {:>5.2}x",
+            name, tiles,
+            ms(totals[0][0]), ms(totals[0][1]), ms(totals[0][0]) / ms(totals[0][1]),
+            ms(totals[1][0]), ms(totals[1][1]), ms(totals[1][0]) / ms(totals[1][1]));
+    }
+}
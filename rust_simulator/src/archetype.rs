@@ -0,0 +1,138 @@
+use wasm_bindgen::prelude::*;
+
+use crate::image::RESOURCES;
+use crate::utlis::INTI_COSTS;
+use crate::{Resource, Terrain, World};
+
+/// Preset empire: growth costs per terrain plus the resources it values above the others
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Archetype {
+    /// cheap on Water and River, after Fish, Spices and Silk
+    Seafaring = 0,
+    /// fast across Plain and Desert, after Cows and Silver
+    SteppeNomad = 1,
+    /// at home in Mountain and Forest, after Gold, Silver, Gems and Coal
+    MountainKingdom = 2,
+    /// follows River through Plain, after Wheat, Wine and Cows
+    RiverCivilisation = 3,
+    /// crosses Desert along rivers, after Spices, Silk and Gems
+    DesertTrader = 4,
+}
+
+pub(crate) const ARCHETYPES: [Archetype; 5] = [
+    Archetype::Seafaring, Archetype::SteppeNomad, Archetype::MountainKingdom,
+    Archetype::RiverCivilisation, Archetype::DesertTrader,
+];
+
+/// Value of each resource (index = Resource) with the `preferences` applied, in percent of
+/// Resource::get_value. Resources not listed keep their value.
+pub(crate) fn resource_values(preferences: &[(Resource, u32)]) -> [u32; 11] {
+    let mut values = RESOURCES.map(|r| r.get_value());
+    for &(resource, percent) in preferences {
+        values[resource as usize] = values[resource as usize] * percent / 100;
+    }
+    values
+}
+
+impl Archetype {
+    /// Name used in map files and shown in the UI
+    pub fn name(self) -> &'static str {
+        match self {
+            Archetype::Seafaring => "seafaring",
+            Archetype::SteppeNomad => "steppe_nomad",
+            Archetype::MountainKingdom => "mountain_kingdom",
+            Archetype::RiverCivilisation => "river_civilisation",
+            Archetype::DesertTrader => "desert_trader",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Archetype> {
+        ARCHETYPES.into_iter().find(|a| a.name() == name)
+    }
+
+    /// INTI_COSTS with the terrains this archetype handles differently
+    pub fn costs(self) -> [u32; 8] {
+        let changes: &[(Terrain, u32)] = match self {
+            Archetype::Seafaring => &[(Terrain::Water, 8), (Terrain::River, 8), (Terrain::Plain, 20), (Terrain::Mountain, 100), (Terrain::Desert, 80), (Terrain::Forest, 30)],
+            Archetype::SteppeNomad => &[(Terrain::Water, 60), (Terrain::River, 15), (Terrain::Plain, 6), (Terrain::Mountain, 120), (Terrain::Desert, 30), (Terrain::Forest, 40)],
+            Archetype::MountainKingdom => &[(Terrain::Water, 50), (Terrain::River, 15), (Terrain::Plain, 25), (Terrain::Mountain, 20), (Terrain::Desert, 80), (Terrain::Forest, 25)],
+            Archetype::RiverCivilisation => &[(Terrain::Water, 40), (Terrain::River, 4), (Terrain::Plain, 10), (Terrain::Mountain, 120), (Terrain::Desert, 80), (Terrain::Forest, 30)],
+            Archetype::DesertTrader => &[(Terrain::Water, 50), (Terrain::River, 8), (Terrain::Plain, 20), (Terrain::Mountain, 100), (Terrain::Desert, 12), (Terrain::Forest, 50)],
+        };
+        let mut costs = INTI_COSTS;
+        for &(terrain, cost) in changes {
+            costs[terrain as usize] = cost;
+        }
+        costs
+    }
+
+    /// Resources weighted differently when growing with resources, in percent
+    pub fn preferences(self) -> &'static [(Resource, u32)] {
+        match self {
+            Archetype::Seafaring => &[(Resource::Fish, 300), (Resource::Spices, 150), (Resource::Silk, 150)],
+            Archetype::SteppeNomad => &[(Resource::Cows, 300), (Resource::Silver, 150)],
+            Archetype::MountainKingdom => &[(Resource::Gold, 200), (Resource::Silver, 200), (Resource::Gems, 200), (Resource::Coal, 250)],
+            Archetype::RiverCivilisation => &[(Resource::Wheat, 300), (Resource::Wine, 200), (Resource::Cows, 150)],
+            Archetype::DesertTrader => &[(Resource::Spices, 250), (Resource::Silk, 250), (Resource::Gems, 150)],
+        }
+    }
+}
+
+/// One entry of World::archetypes, for listing the presets in the UI
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct ArchetypeInfo {
+    pub archetype: Archetype,
+}
+
+#[wasm_bindgen]
+impl ArchetypeInfo {
+    pub fn name(&self) -> String { self.archetype.name().to_string() }
+
+    /// The 8 costs, in the order add_empire takes them
+    pub fn costs(&self) -> Vec<u32> { self.archetype.costs().to_vec() }
+
+    /// Percent applied to each resource's value, index = resource id (100 = unchanged)
+    pub fn resource_preferences(&self) -> Vec<u32> {
+        let mut percents = vec![100; RESOURCES.len()];
+        for &(resource, percent) in self.archetype.preferences() {
+            percents[resource as usize] = percent;
+        }
+        percents
+    }
+}
+
+impl World {
+    /// Tags the empire with the archetype and gives it the archetype's resource values,
+    /// its costs are left alone
+    pub(crate) fn set_archetype(&mut self, empire_id: u32, archetype: Archetype) {
+        if let Some(empire) = self.empires.get_mut(&empire_id) {
+            empire.archetype = Some(archetype);
+            empire.resource_values = resource_values(archetype.preferences());
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Every archetype, in enum order
+    pub fn archetypes() -> Vec<ArchetypeInfo> {
+        ARCHETYPES.into_iter().map(|archetype| ArchetypeInfo { archetype }).collect()
+    }
+
+    /// add_empire with the archetype's costs, the empire then grows towards its preferred
+    /// resources when resources are used. False when the capital is not liveable.
+    pub fn add_empire_with_archetype(&mut self, x: usize, y: usize, empire_id: u32, color: u32, size: u32, archetype: Archetype) -> bool {
+        if !self.add_empire(x, y, empire_id, color, size, archetype.costs().to_vec()) {
+            return false;
+        }
+        self.set_archetype(empire_id, archetype);
+        true
+    }
+
+    /// Archetype the empire was created with, None for hand-built costs
+    pub fn empire_archetype(&self, empire_id: u32) -> Option<Archetype> {
+        self.empires.get(&empire_id).and_then(|empire| empire.archetype)
+    }
+}
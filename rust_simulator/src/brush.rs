@@ -0,0 +1,115 @@
+use wasm_bindgen::prelude::*;
+
+use crate::World;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrushShape {
+    /// disc of diameter / 2 radius, the original brush
+    #[default]
+    Circle = 0,
+    Square = 1,
+    Diamond = 2,
+}
+
+/// Settings shared by `paint_terrain_brush`, `paint_resource_brush` and the line tool.
+/// The defaults give the original hard-edged disc.
+#[derive(Clone, Copy, Debug)]
+pub struct Brush {
+    pub shape: BrushShape,
+    // outer part of the radius where the paint chance drops to 0, 0 is a hard edge
+    pub falloff: f32,
+    // chance of each resource tile under the brush being painted
+    pub density: f32,
+    pub seed: u64,
+    // stamps since the seed was set, so repeated dabs on a tile draw new numbers
+    stamps: u64,
+}
+
+impl Default for Brush {
+    fn default() -> Brush {
+        Brush { shape: BrushShape::Circle, falloff: 0.0, density: 1.0, seed: 0, stamps: 0 }
+    }
+}
+
+/// Splitmix64 finaliser, good enough to decorrelate neighbouring tiles
+pub fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Uniform in [0, 1)
+pub fn unit(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+impl Brush {
+    /// Offsets covered by a brush of this shape, with their distance from the centre
+    /// scaled so the edge tiles stay below 1
+    pub fn footprint(shape: BrushShape, diameter: i32) -> Vec<(i32, i32, f32)> {
+        let radius = (diameter / 2).max(0);
+        let scale = (radius + 1) as f32;
+
+        (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .filter_map(|(dx, dy)| {
+                let distance = match shape {
+                    BrushShape::Circle if dx * dx + dy * dy <= radius * radius => ((dx * dx + dy * dy) as f32).sqrt(),
+                    BrushShape::Square => dx.abs().max(dy.abs()) as f32,
+                    BrushShape::Diamond if dx.abs() + dy.abs() <= radius => (dx.abs() + dy.abs()) as f32,
+                    _ => return None,
+                };
+                Some((dx, dy, distance / scale))
+            })
+            .collect()
+    }
+
+    // Paint chance at a scaled distance, before the density
+    fn chance(&self, distance: f32) -> f32 {
+        let inner = 1.0 - self.falloff;
+        if distance <= inner { 1.0 } else { (1.0 - distance) / self.falloff }
+    }
+}
+
+impl World {
+    /// Tiles picked by one stamp of the brush centred on (center_x, center_y).
+    /// `scatter` applies the brush density on top of the falloff.
+    pub(crate) fn brush_tiles(&mut self, center_x: i32, center_y: i32, diameter: i32, scatter: bool) -> Vec<usize> {
+        let brush = self.brush;
+        self.brush.stamps += 1;
+        let stamp = mix(brush.seed ^ mix(brush.stamps));
+
+        Brush::footprint(brush.shape, diameter)
+            .into_iter()
+            .filter(|&(dx, dy, _)| self.in_map(center_x + dx, center_y + dy))
+            .filter_map(|(dx, dy, distance)| {
+                let index = (center_y + dy) as usize * self.width + (center_x + dx) as usize;
+                let chance = brush.chance(distance) * if scatter { brush.density } else { 1.0 };
+                (chance >= 1.0 || unit(mix(stamp ^ index as u64)) < chance).then_some(index)
+            })
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    pub fn set_brush_shape(&mut self, shape: BrushShape) { self.brush.shape = shape; }
+    pub fn brush_shape(&self) -> BrushShape { self.brush.shape }
+
+    /// Part of the brush radius (0 to 1) over which the paint chance fades out, 0 paints every tile
+    pub fn set_brush_falloff(&mut self, falloff: f32) { self.brush.falloff = falloff.clamp(0.0, 1.0); }
+    pub fn brush_falloff(&self) -> f32 { self.brush.falloff }
+
+    /// Chance (0 to 1) of the resource brush painting each tile, to scatter resources
+    pub fn set_brush_density(&mut self, density: f32) { self.brush.density = density.clamp(0.0, 1.0); }
+    pub fn brush_density(&self) -> f32 { self.brush.density }
+
+    /// Restarts the soft brush and scatter random sequence
+    pub fn set_brush_seed(&mut self, seed: u64) {
+        self.brush.seed = seed;
+        self.brush.stamps = 0;
+    }
+    pub fn brush_seed(&self) -> u64 { self.brush.seed }
+}
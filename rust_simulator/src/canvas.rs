@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::frontier::Frontier;
+use crate::grid::Grid;
+use crate::utlis::{MAX_TILES, map_size};
+use crate::{Resource, Terrain, World};
+
+/// Side or corner of the canvas the map stays attached to in `resize_canvas`
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft = 0,
+    Top = 1,
+    TopRight = 2,
+    Left = 3,
+    Center = 4,
+    Right = 5,
+    BottomLeft = 6,
+    Bottom = 7,
+    BottomRight = 8,
+}
+
+// Everything that moves with the map, laid out for the new size
+struct Layers {
+    width: usize,
+    height: usize,
+    tiles: Vec<Terrain>,
+    resources: Vec<Resource>,
+    owners: Vec<u32>,
+    dist_vector: Vec<u32>,
+    // empire -> new capital index, None when the capital fell off the map
+    capitals: HashMap<u32, Option<usize>>,
+}
+
+// Most frequent value, the first one seen wins ties
+fn majority<T: Copy + PartialEq>(values: impl Iterator<Item = T>) -> T {
+    let mut counts: Vec<(T, u32)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    let best = counts.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    counts.into_iter().find(|(_, count)| *count == best).map(|(v, _)| v).expect("block is not empty")
+}
+
+// Source tiles [start, end) along one axis for new cell `i` of `new` covering `old` tiles,
+// in u64 as the products pass usize on wasm32
+fn block(i: usize, old: usize, new: usize) -> (usize, usize) {
+    let scaled = |i: usize| (i as u64 * old as u64 / new as u64) as usize;
+    let start = scaled(i);
+    (start, scaled(i + 1).max(start + 1))
+}
+
+// The tile count of a new map size, an error when it is empty or over MAX_TILES
+fn new_size(width: usize, height: usize) -> Result<usize, String> {
+    map_size(width as u64, height as u64)
+        .ok_or_else(|| format!("the map can't be {}x{}, it has to hold 1 to {} tiles", width, height, MAX_TILES))
+}
+
+impl World {
+    /// Moves the map into a `width` x `height` canvas, the old tile (x, y) landing on
+    /// (x + dx, y + dy). Tiles that come from outside the old map get `fill`.
+    /// `size` is width * height, checked by new_size.
+    fn shift(&self, width: usize, height: usize, size: usize, dx: i64, dy: i64, fill: Terrain) -> Layers {
+        let mut layers = Layers {
+            width,
+            height,
+            tiles: vec![fill; size],
+            resources: vec![Resource::None; size],
+            owners: vec![0; size],
+            dist_vector: vec![u32::MAX; size],
+            capitals: HashMap::new(),
+        };
+
+        let moved = |x: usize, y: usize| {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+            (nx >= 0 && ny >= 0 && (nx as usize) < width && (ny as usize) < height).then(|| ny as usize * width + nx as usize)
+        };
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let Some(target) = moved(x, y) else { continue };
+                let index = y * self.width + x;
+                layers.tiles[target] = self.tiles[index];
+                layers.resources[target] = self.resources[index];
+                layers.owners[target] = self.owners[index];
+                layers.dist_vector[target] = self.dist_vector[index];
+            }
+        }
+
+        for empire in self.empires.values() {
+            let capital = moved(empire.cap_index % self.width, empire.cap_index / self.width);
+            layers.capitals.insert(empire.id, capital);
+        }
+
+        layers
+    }
+
+    /// Swaps in the new layers. Every size dependent part of the world is built again
+    /// the way the constructor does it, empires whose capital was cut off are removed.
+    fn apply_layers(&mut self, mut layers: Layers) -> Vec<u32> {
+        let (width, height) = (layers.width, layers.height);
+
+        let mut removed: Vec<u32> = layers.capitals.iter().filter(|(_, cap)| cap.is_none()).map(|(&id, _)| id).collect();
+        removed.sort_unstable();
+        for (owner, dist) in layers.owners.iter_mut().zip(layers.dist_vector.iter_mut()) {
+            if removed.contains(owner) {
+                *owner = 0;
+                *dist = u32::MAX;
+            }
+        }
+
+        let mut empires = std::mem::take(&mut self.empires);
+        empires.retain(|id, _| !removed.contains(id));
+        for empire in empires.values_mut() {
+            let capital = layers.capitals[&empire.id].expect("removed above");
+            empire.cap_index = capital;
+            layers.owners[capital] = empire.id;
+            layers.dist_vector[capital] = 0;
+        }
+
+        if self.replay.is_some() {
+            console_log!("Replay cleared, it was recorded on the {}x{} map", self.width, self.height);
+        }
+
+        let mut world = World::from_grids(width, height, layers.tiles, layers.resources, self.chunked);
+        world.owners = Grid::from_vec(layers.owners, width, height, self.chunked);
+        world.dist_vector = Grid::from_vec(layers.dist_vector, width, height, self.chunked);
+        world.frontier = Frontier::rebuild(&world.owners, width, height);
+        world.empires = empires;
+        world.name = std::mem::take(&mut self.name);
+        world.wrap = self.wrap;
+        world.queue_backend = self.queue_backend;
+        world.parallel_growth = self.parallel_growth;
+        world.brush = self.brush;
+
+        *self = world;
+        removed
+    }
+}
+
+/// Size changes. They return the ids of the empires removed because their capital is no
+/// longer on the map. Render buffers are reallocated, so their pointers must be fetched
+/// again, and a recorded replay is dropped.
+#[wasm_bindgen]
+impl World {
+    /// Grows or shrinks the map around `anchor`, new tiles get `fill_terrain`
+    pub fn resize_canvas(&mut self, new_width: usize, new_height: usize, anchor: Anchor, fill_terrain: char) -> Result<Vec<u32>, String> {
+        let size = new_size(new_width, new_height)?;
+        let fill = Terrain::try_from_char(fill_terrain.to_ascii_uppercase())
+            .ok_or_else(|| format!("unknown terrain character `{}`", fill_terrain))?;
+
+        let (column, row) = (anchor as i64 % 3, anchor as i64 / 3);
+        let offset = |old: usize, new: usize, side: i64| (new as i64 - old as i64) * side / 2;
+        let layers = self.shift(new_width, new_height, size, offset(self.width, new_width, column), offset(self.height, new_height, row), fill);
+
+        Ok(self.apply_layers(layers))
+    }
+
+    /// Keeps only the w x h rectangle with its top left corner on (x, y)
+    pub fn crop(&mut self, x: usize, y: usize, w: usize, h: usize) -> Result<Vec<u32>, String> {
+        if w == 0 || h == 0 || x.saturating_add(w) > self.width || y.saturating_add(h) > self.height {
+            return Err(format!("{}x{} at ({}, {}) is not inside the {}x{} map", w, h, x, y, self.width, self.height));
+        }
+
+        let size = new_size(w, h)?;
+        let layers = self.shift(w, h, size, -(x as i64), -(y as i64), Terrain::Water);
+        Ok(self.apply_layers(layers))
+    }
+
+    /// Scales the map by `factor`. Each new tile takes the majority terrain, resource and owner
+    /// of the old tiles it covers (a single one when enlarging), and the lowest growth cost
+    /// among the tiles of that owner. Capitals keep their relative position.
+    pub fn rescale(&mut self, factor: f32) -> Result<Vec<u32>, String> {
+        if !(factor.is_finite() && factor > 0.0) {
+            return Err(format!("invalid scale factor {}", factor));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err("the map is empty".to_string());
+        }
+        let width = ((self.width as f32 * factor).round() as usize).max(1);
+        let height = ((self.height as f32 * factor).round() as usize).max(1);
+        let size = new_size(width, height)?;
+
+        let (old_width, old_height) = (self.width, self.height);
+        let world = &*self;
+        let cells: Vec<(Terrain, Resource, u32, u32)> = (0..size)
+            .into_par_iter()
+            .map(|i| {
+                let (x0, x1) = block(i % width, old_width, width);
+                let (y0, y1) = block(i / width, old_height, height);
+                let covered = || (y0..y1).flat_map(move |y| (x0..x1).map(move |x| y * old_width + x));
+
+                let owner = majority(covered().map(|index| world.owners[index]));
+                let dist = covered()
+                    .filter(|&index| owner != 0 && world.owners[index] == owner)
+                    .map(|index| world.dist_vector[index])
+                    .min()
+                    .unwrap_or(u32::MAX);
+                (majority(covered().map(|index| world.tiles[index])), majority(covered().map(|index| world.resources[index])), owner, dist)
+            })
+            .collect();
+
+        let mut layers = Layers {
+            width,
+            height,
+            tiles: cells.iter().map(|c| c.0).collect(),
+            resources: cells.iter().map(|c| c.1).collect(),
+            owners: cells.iter().map(|c| c.2).collect(),
+            dist_vector: cells.iter().map(|c| c.3).collect(),
+            capitals: HashMap::new(),
+        };
+        for empire in self.empires.values() {
+            let (x, y) = (empire.cap_index % old_width, empire.cap_index / old_width);
+            let capital = block(y, height, old_height).0 * width + block(x, width, old_width).0;
+            layers.capitals.insert(empire.id, Some(capital));
+        }
+
+        Ok(self.apply_layers(layers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COSTS: [u32; 8] = [9999, 25, 10, 15, 80, 60, 20, 100];
+
+    // 8x6 plains with empire 1 on (5, 4) and empire 2 on (1, 1), each grown a little
+    fn two_empires() -> World {
+        let mut world = World::new(&"PPPPPPPP\n".repeat(6), None);
+        assert!(world.add_empire(5, 4, 1, 0xFF0000FF, 20, COSTS.to_vec()));
+        assert!(world.add_empire(1, 1, 2, 0xFFFF0000, 20, COSTS.to_vec()));
+        world.auto_grow(30, true);
+        world
+    }
+
+    fn capital(world: &World, id: u32) -> (usize, usize) {
+        let index = world.empires[&id].cap_index;
+        (index % world.width, index / world.width)
+    }
+
+    fn assert_capital(world: &World, id: u32, at: (usize, usize)) {
+        assert_eq!(capital(world, id), at);
+        let index = at.1 * world.width + at.0;
+        assert_eq!((world.owners[index], world.dist_vector[index]), (id, 0));
+    }
+
+    #[test]
+    fn crop_moves_capitals_and_drops_the_ones_cut_off() {
+        let mut world = two_empires();
+        assert_eq!(world.crop(2, 2, 5, 4), Ok(vec![2]));
+
+        assert_eq!((world.width, world.height), (5, 4));
+        assert_capital(&world, 1, (3, 2));
+        assert!(!world.empires.contains_key(&2));
+        assert!(world.owners.indices_where(|owner| owner == 2).is_empty());
+        assert!(world.owners.indices_where(|owner| owner == 1).len() > 1);
+
+        assert!(world.crop(1, 0, 5, 1).is_err());
+        assert!(world.crop(0, 0, 0, 1).is_err());
+        assert_eq!((world.width, world.height), (5, 4));
+    }
+
+    #[test]
+    fn resize_shifts_capitals_with_the_anchor() {
+        let mut world = two_empires();
+        assert_eq!(world.resize_canvas(10, 9, Anchor::BottomRight, 'w'), Ok(vec![]));
+        assert_capital(&world, 1, (7, 7));
+        assert_capital(&world, 2, (3, 4));
+        assert_eq!(world.tiles[0], Terrain::Water);
+        assert_eq!(world.tiles[9 * 10 - 1], Terrain::Plain);
+
+        // shrinking around the centre cuts one column and row off each side
+        assert_eq!(world.resize_canvas(8, 7, Anchor::Center, 'w'), Ok(vec![]));
+        assert_capital(&world, 1, (6, 6));
+        assert_capital(&world, 2, (2, 3));
+        assert_eq!(world.resize_canvas(4, 4, Anchor::TopLeft, 'w'), Ok(vec![1]));
+        assert_capital(&world, 2, (2, 3));
+    }
+
+    #[test]
+    fn sizes_outside_the_tile_limit_are_errors() {
+        let mut world = two_empires();
+        assert!(world.resize_canvas(0, 5, Anchor::TopLeft, 'w').is_err());
+        assert!(world.resize_canvas(1 << 14, 1 << 13, Anchor::TopLeft, 'w').is_err());
+        assert!(world.resize_canvas(usize::MAX, usize::MAX, Anchor::TopLeft, 'w').is_err());
+        assert!(world.resize_canvas(5, 5, Anchor::TopLeft, 'x').is_err());
+        assert!(world.rescale(1e6).is_err());
+        assert!(world.rescale(f32::NAN).is_err());
+        assert!(world.rescale(0.0).is_err());
+        assert_eq!((world.width, world.height, world.empires.len()), (8, 6, 2));
+    }
+
+    #[test]
+    fn rescale_keeps_capitals_in_place() {
+        let mut world = two_empires();
+        assert_eq!(world.rescale(2.0), Ok(vec![]));
+        assert_eq!((world.width, world.height), (16, 12));
+        assert_capital(&world, 1, (10, 8));
+        assert_capital(&world, 2, (2, 2));
+
+        let mut world = two_empires();
+        assert_eq!(world.rescale(0.5), Ok(vec![]));
+        assert_eq!((world.width, world.height), (4, 3));
+        assert_capital(&world, 1, (2, 2));
+        assert_capital(&world, 2, (0, 0));
+    }
+}
@@ -0,0 +1,251 @@
+use wasm_bindgen::prelude::*;
+
+use crate::dirty::Layer;
+use crate::mapfile::{MAP_FORMAT_VERSION, MapFile};
+use crate::{Resource, Terrain, World};
+
+/// Terrain and resources of a rectangle, copied with `World::copy_region`
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct Clipboard {
+    width: usize,
+    height: usize,
+    tiles: Vec<Terrain>,
+    resources: Vec<Resource>,
+}
+
+/// What `paste_region` writes
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasteMode {
+    /// every tile, terrain and resource
+    Overwrite = 0,
+    /// only the clipboard's land tiles, its water is left out
+    LandOnly = 1,
+    /// resources only, on the liveable tiles of the map
+    ResourcesOnly = 2,
+}
+
+impl Clipboard {
+    // Rebuilds the clipboard with `source(x, y)` giving the old index of each new tile
+    fn remap(&self, width: usize, height: usize, source: impl Fn(usize, usize) -> usize) -> Clipboard {
+        let indices: Vec<usize> = (0..width * height).map(|i| source(i % width, i / width)).collect();
+        Clipboard {
+            width,
+            height,
+            tiles: indices.iter().map(|&i| self.tiles[i]).collect(),
+            resources: indices.iter().map(|&i| self.resources[i]).collect(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Clipboard {
+    pub fn width(&self) -> usize { self.width }
+    pub fn height(&self) -> usize { self.height }
+
+    /// Copy turned clockwise by `quarter_turns` (negative turns go counterclockwise)
+    pub fn rotated(&self, quarter_turns: i32) -> Clipboard {
+        let (w, h) = (self.width, self.height);
+        match quarter_turns.rem_euclid(4) {
+            1 => self.remap(h, w, |x, y| (h - 1 - x) * w + y),
+            2 => self.remap(w, h, |x, y| (h - 1 - y) * w + (w - 1 - x)),
+            3 => self.remap(h, w, |x, y| x * w + (w - 1 - y)),
+            _ => self.clone(),
+        }
+    }
+
+    /// Copy flipped left to right, or top to bottom when `horizontal` is false
+    pub fn mirrored(&self, horizontal: bool) -> Clipboard {
+        let (w, h) = (self.width, self.height);
+        if horizontal {
+            self.remap(w, h, |x, y| y * w + (w - 1 - x))
+        } else {
+            self.remap(w, h, |x, y| (h - 1 - y) * w + x)
+        }
+    }
+
+    /// The clipboard as a version 2 map text, so stamps can be saved and loaded like maps
+    pub fn to_text(&self) -> String {
+        let mut output = String::with_capacity((self.width + 1) * self.height * 2 + 64);
+        output.push_str(&format!("version: {}\nname: stamp\nwidth: {}\nheight: {}\n", MAP_FORMAT_VERSION, self.width, self.height));
+
+        output.push_str("\n[terrain]\n");
+        for row in self.tiles.chunks(self.width) {
+            output.extend(row.iter().map(|t| t.to_char()));
+            output.push('\n');
+        }
+        output.push_str("\n[resources]\n");
+        for row in self.resources.chunks(self.width) {
+            output.extend(row.iter().map(|r| r.to_char()));
+            output.push('\n');
+        }
+
+        output
+    }
+
+    /// Reads a stamp written by `to_text`, or any map text (its empires are ignored)
+    pub fn from_text(text: &str) -> Result<Clipboard, String> {
+        let map = MapFile::parse(text).map_err(|e| e.to_string())?;
+        if map.width == 0 || map.height == 0 {
+            return Err("the stamp is empty".to_string());
+        }
+
+        Ok(Clipboard {
+            width: map.width,
+            height: map.height,
+            resources: map.resources.unwrap_or_else(|| vec![Resource::None; map.width * map.height]),
+            tiles: map.tiles,
+        })
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Copies the w x h rectangle with its top left corner on (x, y)
+    pub fn copy_region(&self, x: usize, y: usize, w: usize, h: usize) -> Result<Clipboard, String> {
+        if w == 0 || h == 0 || x.saturating_add(w) > self.width || y.saturating_add(h) > self.height {
+            return Err(format!("{}x{} at ({}, {}) is not inside the {}x{} map", w, h, x, y, self.width, self.height));
+        }
+
+        let indices = (y..y + h).flat_map(|row| (x..x + w).map(move |col| row * self.width + col));
+        Ok(Clipboard {
+            width: w,
+            height: h,
+            tiles: indices.clone().map(|i| self.tiles[i]).collect(),
+            resources: indices.map(|i| self.resources[i]).collect(),
+        })
+    }
+
+    /// Pastes the clipboard with its top left corner on (x, y), the parts outside the map
+    /// are dropped. Returns the number of tiles written.
+    pub fn paste_region(&mut self, clip: &Clipboard, x: i32, y: i32, mode: PasteMode) -> u32 {
+        let mut written = 0;
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+
+        // clipboard columns and rows that land on the map, in i64 so no offset can overflow
+        let overlap = |at: i32, len: usize, side: usize| {
+            let start = (-(at as i64)).clamp(0, len as i64);
+            (start as usize, (side as i64 - at as i64).clamp(start, len as i64) as usize)
+        };
+        let (columns, rows) = (overlap(x, clip.width, self.width), overlap(y, clip.height, self.height));
+
+        for cy in rows.0..rows.1 {
+            for cx in columns.0..columns.1 {
+                let (mx, my) = ((x as i64 + cx as i64) as usize, (y as i64 + cy as i64) as usize);
+
+                let source = cy * clip.width + cx;
+                let index = my * self.width + mx;
+                let (terrain, resource) = (clip.tiles[source], clip.resources[source]);
+
+                match mode {
+                    PasteMode::Overwrite => {}
+                    PasteMode::LandOnly if terrain.is_liveable() && !terrain.is_watery() => {}
+                    PasteMode::ResourcesOnly if self.tiles[index].is_liveable() => {}
+                    _ => continue,
+                }
+
+                if mode != PasteMode::ResourcesOnly {
+                    self.tiles.set(index, terrain);
+                    if let Some(pixel) = self.terrain_buffer.get_mut(index) {
+                        *pixel = terrain.get_color();
+                    }
+                }
+                self.resources.set(index, resource);
+                if let Some(pixel) = self.resource_buffer.get_mut(index) {
+                    *pixel = resource.get_color();
+                }
+
+                bounds = Some(match bounds {
+                    Some((x0, y0, x1, y1)) => (x0.min(mx), y0.min(my), x1.max(mx), y1.max(my)),
+                    None => (mx, my, mx, my),
+                });
+                written += 1;
+            }
+        }
+
+        if let Some((x0, y0, x1, y1)) = bounds {
+            self.dirty.mark(Layer::Resources, x0, y0, x1, y1, 0);
+            if mode != PasteMode::ResourcesOnly {
+                self.dirty.mark(Layer::Terrain, x0, y0, x1, y1, 0);
+                // coastline outlines of the neighbours change too
+                self.dirty.mark(Layer::Borders, x0, y0, x1, y1, 1);
+            }
+        }
+
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x2 stamp with no symmetry, so every turn and flip is told apart
+    fn stamp() -> Clipboard {
+        Clipboard::from_text("version: 2\nwidth: 3\nheight: 2\n[terrain]\nWPM\nDFI\n[resources]\ng..\n..w\n").unwrap()
+    }
+
+    fn terrain(clip: &Clipboard) -> Vec<String> {
+        clip.tiles.chunks(clip.width).map(|row| row.iter().map(|t| t.to_char()).collect()).collect()
+    }
+
+    #[test]
+    fn four_quarter_turns_are_the_identity() {
+        let clip = stamp();
+        let mut turned = clip.clone();
+        for _ in 0..4 {
+            turned = turned.rotated(1);
+        }
+        assert_eq!(turned.to_text(), clip.to_text());
+        assert_eq!(clip.rotated(-4).to_text(), clip.to_text());
+        assert_eq!(clip.rotated(-1).to_text(), clip.rotated(3).to_text());
+        assert_eq!(clip.rotated(1).rotated(-1).to_text(), clip.to_text());
+    }
+
+    #[test]
+    fn turns_and_flips_move_the_tiles() {
+        let clip = stamp();
+        let quarter = clip.rotated(1);
+        assert_eq!((quarter.width(), quarter.height()), (2, 3));
+        assert_eq!(terrain(&quarter), ["DW", "FP", "IM"]);
+        assert_eq!(quarter.resources[1], Resource::Gold);
+        assert_eq!(terrain(&clip.rotated(3)), ["MI", "PF", "WD"]);
+
+        assert_eq!(terrain(&clip.mirrored(true)), ["MPW", "IFD"]);
+        assert_eq!(terrain(&clip.mirrored(false)), ["DFI", "WPM"]);
+        assert_eq!(clip.mirrored(true).mirrored(true).to_text(), clip.to_text());
+        assert_eq!(clip.mirrored(false).mirrored(false).to_text(), clip.to_text());
+        assert_eq!(clip.mirrored(true).mirrored(false).to_text(), clip.rotated(2).to_text());
+    }
+
+    #[test]
+    fn pastes_are_clipped_to_the_map() {
+        let map = "PPPP\nPPPP\nPPPP\n";
+        let clip = stamp();
+
+        let mut world = World::new(map, None);
+        assert_eq!(world.paste_region(&clip, -1, 2, PasteMode::Overwrite), 2);
+        assert_eq!(world.export_map_to_string(false), "PPPP\nPPPP\nPMPP\n");
+        assert_eq!(world.paste_region(&clip, 3, -1, PasteMode::Overwrite), 1);
+        assert_eq!(world.export_map_to_string(false), "PPPD\nPPPP\nPMPP\n");
+
+        for (x, y) in [(i32::MIN, 0), (i32::MAX, 0), (0, i32::MIN), (0, i32::MAX), (-3, 0), (4, 0)] {
+            assert_eq!(world.paste_region(&clip, x, y, PasteMode::Overwrite), 0, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn paste_modes_pick_the_tiles_written() {
+        let clip = stamp();
+
+        let mut world = World::new("WWW\nPPP\n", None);
+        assert_eq!(world.paste_region(&clip, 0, 0, PasteMode::LandOnly), 5);
+        assert_eq!(world.export_map_to_string(false), "WPM\nDFI\n");
+
+        let mut world = World::new("WWW\nPPP\n", None);
+        assert_eq!(world.paste_region(&clip, 0, 0, PasteMode::ResourcesOnly), 3);
+        assert_eq!(world.export_map_to_string(false), "WWW\nPPP\n");
+        assert_eq!(world.export_resource_to_string(false), "...\n..w\n");
+    }
+}
@@ -0,0 +1,171 @@
+use wasm_bindgen::prelude::*;
+
+use crate::brush::mix;
+use crate::dirty::{Bounds, Layer, par_render_rect, rect};
+use crate::{Resource, Terrain, World, ensure_buffer};
+
+// Per component totals, index = label - 1
+#[derive(Clone, Copy)]
+struct Stats {
+    water: bool,
+    area: u32,
+    bounds: Bounds,
+    resource_value: u64,
+    resource_counts: [u32; 11],
+}
+
+/// Landmasses and water bodies: 4-connected land tiles (liveable, not watery) or watery
+/// tiles (Water and River), labelled from 1. Rivers can be counted as land instead, so they
+/// don't cut continents apart. Unknown tiles belong to neither and have label 0.
+/// A snapshot, later map edits don't change it.
+#[wasm_bindgen]
+pub struct Components {
+    width: usize,
+    height: usize,
+    labels: Vec<u32>,
+    stats: Vec<Stats>,
+}
+
+impl Components {
+    fn stats(&self, label: u32) -> Option<&Stats> {
+        (label as usize).checked_sub(1).and_then(|i| self.stats.get(i))
+    }
+}
+
+#[wasm_bindgen]
+impl Components {
+    pub fn count(&self) -> u32 { self.stats.len() as u32 }
+
+    /// Label of the tile, 0 for Unknown tiles and outside the map
+    pub fn label_at(&self, x: usize, y: usize) -> u32 {
+        if x >= self.width || y >= self.height { return 0; }
+        self.labels[y * self.width + x]
+    }
+
+    /// Whether the two tiles are on the same landmass or in the same water body
+    pub fn connected(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> bool {
+        let label = self.label_at(x0, y0);
+        label != 0 && label == self.label_at(x1, y1)
+    }
+
+    pub fn is_water(&self, label: u32) -> bool { self.stats(label).is_some_and(|s| s.water) }
+    pub fn area(&self, label: u32) -> u32 { self.stats(label).map_or(0, |s| s.area) }
+
+    /// [min_x, min_y, max_x, max_y], inclusive, empty for an unknown label
+    pub fn bounds(&self, label: u32) -> Vec<usize> {
+        self.stats(label).map_or(Vec::new(), |s| vec![s.bounds.min_x, s.bounds.min_y, s.bounds.max_x, s.bounds.max_y])
+    }
+
+    /// Sum of the resource values on the component
+    pub fn resource_value(&self, label: u32) -> u64 { self.stats(label).map_or(0, |s| s.resource_value) }
+
+    /// Number of tiles holding `resource_val` on the component
+    pub fn resource_count(&self, label: u32, resource_val: char) -> u32 {
+        match (self.stats(label), Resource::try_from_char(resource_val)) {
+            (Some(s), Some(resource)) => s.resource_counts[resource as usize],
+            _ => 0,
+        }
+    }
+
+    /// Labels of the landmasses (or water bodies) from the largest down
+    pub fn by_area(&self, water: bool) -> Vec<u32> {
+        let mut labels: Vec<u32> = (1..=self.count()).filter(|&l| self.is_water(l) == water).collect();
+        labels.sort_by_key(|&l| std::cmp::Reverse(self.area(l)));
+        labels
+    }
+}
+
+// Water (true), land (false) or neither (Unknown)
+fn class(terrain: Terrain, rivers_as_land: bool) -> Option<bool> {
+    if terrain == Terrain::River && rivers_as_land {
+        Some(false)
+    } else if terrain.is_watery() {
+        Some(true)
+    } else if terrain.is_liveable() {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Labels the landmasses and water bodies of the current terrain
+    pub fn components(&self, rivers_as_land: bool) -> Components {
+        let (width, height) = (self.width, self.height);
+        let tiles = self.tiles.to_vec();
+        let mut labels = vec![0u32; width * height];
+        let mut stats = Vec::new();
+        let mut stack = Vec::new();
+
+        for start in 0..tiles.len() {
+            if labels[start] != 0 { continue; }
+            let Some(water) = class(tiles[start], rivers_as_land) else { continue };
+
+            let label = stats.len() as u32 + 1;
+            let (x, y) = (start % width, start / width);
+            let mut s = Stats {
+                water,
+                area: 0,
+                bounds: Bounds { min_x: x, min_y: y, max_x: x, max_y: y },
+                resource_value: 0,
+                resource_counts: [0; 11],
+            };
+            labels[start] = label;
+            stack.push(start);
+
+            while let Some(index) = stack.pop() {
+                let (x, y) = (index % width, index / width);
+                let resource = self.resources[index];
+                s.area += 1;
+                s.bounds = s.bounds.union(Bounds { min_x: x, min_y: y, max_x: x, max_y: y });
+                s.resource_value += resource.get_value() as u64;
+                s.resource_counts[resource as usize] += 1;
+
+                let neighbours = [
+                    (x > 0).then(|| index - 1),
+                    (x + 1 < width).then(|| index + 1),
+                    (y > 0).then(|| index - width),
+                    (y + 1 < height).then(|| index + width),
+                ];
+                for next in neighbours.into_iter().flatten() {
+                    if labels[next] == 0 && class(tiles[next], rivers_as_land) == Some(water) {
+                        labels[next] = label;
+                        stack.push(next);
+                    }
+                }
+            }
+            stats.push(s);
+        }
+
+        Components { width, height, labels, stats }
+    }
+
+    /// Draws every landmass and water body in its own colour into the components buffer,
+    /// shown by RenderLayer::Components in the composite.
+    pub fn render_components(&mut self, rivers_as_land: bool) -> Vec<u32> {
+        let size = self.width * self.height;
+        if size == 0 {
+            return Vec::new();
+        }
+        let components = self.components(rivers_as_land);
+        ensure_buffer(&mut self.components_buffer, size);
+
+        let full = Bounds { min_x: 0, min_y: 0, max_x: self.width - 1, max_y: self.height - 1 };
+        par_render_rect(&mut self.components_buffer, self.width, full, |index, pixel| {
+            *pixel = match components.labels[index] {
+                0 => 0x00000000,
+                label => {
+                    let hash = mix(label as u64) as u32;
+                    // water bodies darker so they read as water next to land
+                    let base = if components.is_water(label) { 0x40 } else { 0x80 };
+                    let channel = |shift: u32| (base + ((hash >> shift) & 0x7F)) << shift;
+                    0xFF000000 | channel(16) | channel(8) | channel(0)
+                }
+            };
+        });
+        self.dirty.mark_layer(Layer::Composite);
+
+        rect(Some(full))
+    }
+}
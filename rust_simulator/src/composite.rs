@@ -0,0 +1,127 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{World, ensure_buffer};
+use crate::dirty::{Bounds, Layer, par_render_rect, rect};
+
+/// Bits for the `layer_mask` of `render_composite`, combine with `|`
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum RenderLayer {
+    Terrain = 1,
+    Distance = 2,
+    Ownership = 4,
+    Resources = 8,
+    Borders = 16,
+    Components = 32,
+}
+
+impl RenderLayer {
+    fn is_in(self, mask: u32) -> bool {
+        mask & self as u32 != 0
+    }
+}
+
+// Source-over blend of two 0xAABBGGRR colors, `src` alpha is scaled by `opacity` (0..=255)
+pub(crate) fn blend_over(dst: u32, src: u32, opacity: u32) -> u32 {
+    let src_a = ((src >> 24) * opacity) / 255;
+    if src_a == 0 {
+        return dst;
+    }
+    if src_a == 255 {
+        return src | 0xFF000000;
+    }
+
+    let dst_a = dst >> 24;
+    let inv = 255 - src_a;
+    let out_a = src_a + (dst_a * inv) / 255;
+
+    let channel = |shift: u32| {
+        let s = (src >> shift) & 0xFF;
+        let d = (dst >> shift) & 0xFF;
+        // weights are premultiplied then divided back by the output alpha
+        let value = (s * src_a * 255 + d * dst_a * inv) / (out_a * 255);
+        value.min(0xFF) << shift
+    };
+
+    (out_a << 24) | channel(16) | channel(8) | channel(0)
+}
+
+// Ownership alpha (0.0 - 1.0) as the 0..=255 opacity blend_over takes
+pub(crate) fn ownership_opacity(ownership_alpha: f32) -> u32 {
+    (ownership_alpha.clamp(0.0, 1.0) * 255.0).round() as u32
+}
+
+impl World {
+    /// Redraws the stale parts of the layer buffers selected in `layer_mask`. Like render_* but
+    /// leaves the rects for the layers' own canvases in place.
+    pub(crate) fn refresh_layers(&mut self, layer_mask: u32) {
+        if RenderLayer::Terrain.is_in(layer_mask) { self.refresh_terrain(); }
+        if RenderLayer::Ownership.is_in(layer_mask) { self.refresh_ownership(); }
+        if RenderLayer::Resources.is_in(layer_mask) { self.refresh_resources(); }
+        if RenderLayer::Borders.is_in(layer_mask) {
+            let (border_color, coastline_color) = self.border_style.unwrap_or((None, None));
+            self.refresh_borders(border_color, coastline_color);
+        }
+    }
+
+    /// Blends the selected layers over `bounds` of `out`, a map sized buffer.
+    /// Bottom to top: terrain, distance, components, ownership, resources, borders.
+    pub(crate) fn blend_layers(&mut self, layer_mask: u32, opacity: u32, bounds: Bounds, out: &mut [u32]) {
+        // chunked worlds allocate them on first use, distance and components stay transparent
+        // until render_dist_map and render_components
+        ensure_buffer(&mut self.dist_buffer, self.width * self.height);
+        ensure_buffer(&mut self.components_buffer, self.width * self.height);
+
+        // (buffer, opacity) from the bottom up, unselected layers are skipped
+        let layers: Vec<(&[u32], u32)> = [
+            (RenderLayer::Terrain, &self.terrain_buffer, 255),
+            (RenderLayer::Distance, &self.dist_buffer, 255),
+            (RenderLayer::Components, &self.components_buffer, 255),
+            (RenderLayer::Ownership, &self.ownership_buffer, opacity),
+            (RenderLayer::Resources, &self.resource_buffer, 255),
+            (RenderLayer::Borders, &self.border_buffer, 255),
+        ]
+            .into_iter()
+            .filter(|(layer, _, _)| layer.is_in(layer_mask))
+            .map(|(_, buffer, layer_opacity)| (buffer.as_slice(), layer_opacity))
+            .collect();
+
+        par_render_rect(out, self.width, bounds, |index, pixel| {
+            let mut color = 0x00000000;
+            for &(buffer, layer_opacity) in &layers {
+                color = blend_over(color, buffer[index], layer_opacity);
+            }
+            *pixel = color;
+        });
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Blends the layers selected in `layer_mask` (see RenderLayer) into the composite buffer.
+    /// Bottom to top: terrain, distance, components, ownership, resources, borders.
+    /// `ownership_alpha` (0.0 - 1.0) makes the empire colors translucent over the terrain.
+    ///
+    /// Terrain, ownership, resources and borders are refreshed here, the distance layer
+    /// keeps whatever the last render_dist_map produced. The per-layer render_* calls still
+    /// return what was refreshed here, so per-layer canvases can be mixed with the composite.
+    pub fn render_composite(&mut self, layer_mask: u32, ownership_alpha: f32) -> Vec<u32> {
+        let opacity = ownership_opacity(ownership_alpha);
+        self.refresh_layers(layer_mask);
+
+        if self.composite_style != Some((layer_mask, opacity)) {
+            self.composite_style = Some((layer_mask, opacity));
+            self.dirty.mark_layer(Layer::Composite);
+        }
+        let Some(bounds) = self.dirty.take(Layer::Composite) else { return Vec::new() };
+
+        // chunked worlds allocate it on first use
+        ensure_buffer(&mut self.composite_buffer, self.width * self.height);
+        let mut composite = std::mem::take(&mut self.composite_buffer);
+        self.blend_layers(layer_mask, opacity, bounds, &mut composite);
+        self.composite_buffer = composite;
+
+        rect(Some(bounds))
+    }
+}
@@ -0,0 +1,134 @@
+use rayon::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Terrain,
+    Ownership,
+    Resources,
+    Borders,
+    Composite,
+}
+
+// Inclusive tile bounds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub min_x: usize,
+    pub min_y: usize,
+    pub max_x: usize,
+    pub max_y: usize,
+}
+
+impl Bounds {
+    pub fn union(self, other: Bounds) -> Bounds {
+        Bounds {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+}
+
+/// Area of a render buffer that changed, in tiles, as the `[x, y, width, height]` the render
+/// functions return to JS for `putImageData(img, 0, 0, x, y, width, height)`.
+/// Empty when nothing changed.
+pub fn rect(bounds: Option<Bounds>) -> Vec<u32> {
+    match bounds {
+        Some(b) => vec![b.min_x as u32, b.min_y as u32, (b.max_x - b.min_x + 1) as u32, (b.max_y - b.min_y + 1) as u32],
+        None => Vec::new(),
+    }
+}
+
+const LAYERS: [Layer; 5] = [Layer::Terrain, Layer::Ownership, Layer::Resources, Layer::Borders, Layer::Composite];
+
+fn union(slot: &mut Option<Bounds>, bounds: Bounds) {
+    *slot = Some(match *slot {
+        Some(current) => current.union(bounds),
+        None => bounds,
+    });
+}
+
+/// Bounding boxes of pending changes per render buffer, in two stages. `stale` is the part of
+/// the buffer that no longer matches the map, cleared when the buffer is redrawn. `drawn` is
+/// the part redrawn since the layer's own canvas last fetched it. The composite redraws the
+/// layer buffers it blends without taking `drawn`, so the per-layer canvases still get their rect.
+#[derive(Default)]
+pub struct DirtyRegions {
+    width: usize,
+    height: usize,
+    stale: [Option<Bounds>; 5],
+    drawn: [Option<Bounds>; 5],
+}
+
+impl DirtyRegions {
+    /// Everything starts dirty so the first render of each layer is complete
+    pub fn new(width: usize, height: usize) -> DirtyRegions {
+        let mut regions = DirtyRegions { width, height, ..Default::default() };
+        regions.mark_all();
+        regions
+    }
+
+    /// Marks the inclusive box, `pad` extra tiles on each side (clamped to the map)
+    pub fn mark(&mut self, layer: Layer, min_x: usize, min_y: usize, max_x: usize, max_y: usize, pad: usize) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        let bounds = Bounds {
+            min_x: min_x.saturating_sub(pad),
+            min_y: min_y.saturating_sub(pad),
+            max_x: (max_x + pad).min(self.width - 1),
+            max_y: (max_y + pad).min(self.height - 1),
+        };
+
+        union(&mut self.stale[layer as usize], bounds);
+    }
+
+    pub fn mark_bounds(&mut self, layer: Layer, b: Bounds) {
+        self.mark(layer, b.min_x, b.min_y, b.max_x, b.max_y, 0);
+    }
+
+    pub fn mark_layer(&mut self, layer: Layer) {
+        let (max_x, max_y) = (self.width.saturating_sub(1), self.height.saturating_sub(1));
+        self.mark(layer, 0, 0, max_x, max_y, 0);
+    }
+
+    pub fn mark_all(&mut self) {
+        for layer in LAYERS {
+            self.mark_layer(layer);
+        }
+    }
+
+    /// Stale area of the layer's buffer, the caller is about to redraw it
+    pub fn take(&mut self, layer: Layer) -> Option<Bounds> {
+        self.stale[layer as usize].take()
+    }
+
+    /// Records that `bounds` of the layer's buffer were redrawn
+    pub fn drawn(&mut self, layer: Layer, bounds: Bounds) {
+        union(&mut self.drawn[layer as usize], bounds);
+    }
+
+    /// Area redrawn since the last call, for the layer's canvas
+    pub fn take_drawn(&mut self, layer: Layer) -> Option<Bounds> {
+        self.drawn[layer as usize].take()
+    }
+}
+
+/// Runs `paint(tile_index, pixel)` in parallel over the pixels inside `bounds`.
+/// Rows are handed to the workers whole, only the dirty columns are touched.
+pub fn par_render_rect<F>(buffer: &mut [u32], width: usize, bounds: Bounds, paint: F)
+where
+    F: Fn(usize, &mut u32) + Sync + Send,
+{
+    let rows = &mut buffer[bounds.min_y * width..(bounds.max_y + 1) * width];
+
+    rows.par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(row, line)| {
+            let row_start = (bounds.min_y + row) * width;
+            for (x, pixel) in line[bounds.min_x..=bounds.max_x].iter_mut().enumerate() {
+                paint(row_start + bounds.min_x + x, pixel);
+            }
+        });
+}
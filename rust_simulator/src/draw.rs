@@ -0,0 +1,278 @@
+use wasm_bindgen::prelude::*;
+
+use crate::brush::Brush;
+use crate::dirty::Layer;
+use crate::{Resource, Terrain, World};
+
+fn parse_terrain(terrain_val: char) -> Result<Terrain, String> {
+    Terrain::try_from_char(terrain_val.to_ascii_uppercase())
+        .ok_or_else(|| format!("unknown terrain character `{}`", terrain_val))
+}
+
+fn parse_resource(resource_val: char) -> Result<Resource, String> {
+    Resource::try_from_char(resource_val)
+        .ok_or_else(|| format!("unknown resource character `{}`", resource_val))
+}
+
+impl World {
+    pub(crate) fn in_map(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+
+    /// Tiles under the brush dragged from (x0, y0) to (x1, y1): Bresenham steps, the brush
+    /// shape stamped hard-edged on each. Clipped to the map, sorted and without duplicates.
+    fn line_tiles(&self, x0: i32, y0: i32, x1: i32, y1: i32, diameter: i32) -> Vec<usize> {
+        let stamp = Brush::footprint(self.brush.shape, diameter);
+        let radius = (diameter / 2).max(0) as i64;
+        let Some(((x0, y0), (x1, y1))) = self.clip_segment((x0 as i64, y0 as i64), (x1 as i64, y1 as i64), radius) else {
+            return Vec::new();
+        };
+
+        let mut tiles = Vec::new();
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (step_x, step_y) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+        loop {
+            for &(sx, sy, _) in &stamp {
+                let (tx, ty) = (x + sx as i64, y + sy as i64);
+                if tx >= 0 && ty >= 0 && (tx as usize) < self.width && (ty as usize) < self.height {
+                    tiles.push(ty as usize * self.width + tx as usize);
+                }
+            }
+            if x == x1 && y == y1 { break; }
+
+            let doubled = 2 * error;
+            if doubled >= dy { error += dy; x += step_x; }
+            if doubled <= dx { error += dx; y += step_y; }
+        }
+
+        tiles.sort_unstable();
+        tiles.dedup();
+        tiles
+    }
+
+    /// The part of the segment within `margin` tiles of the map (Liang-Barsky), its ends
+    /// rounded to tiles. None when it stays farther out.
+    fn clip_segment(&self, from: (i64, i64), to: (i64, i64), margin: i64) -> Option<((i64, i64), (i64, i64))> {
+        let (min_x, max_x) = (-margin, self.width as i64 - 1 + margin);
+        let (min_y, max_y) = (-margin, self.height as i64 - 1 + margin);
+        let (dx, dy) = ((to.0 - from.0) as f64, (to.1 - from.1) as f64);
+
+        let (mut enter, mut leave) = (0.0f64, 1.0f64);
+        for (p, q) in [
+            (-dx, (from.0 - min_x) as f64), (dx, (max_x - from.0) as f64),
+            (-dy, (from.1 - min_y) as f64), (dy, (max_y - from.1) as f64),
+        ] {
+            if p == 0.0 {
+                if q < 0.0 { return None; }
+            } else if p < 0.0 {
+                enter = enter.max(q / p);
+            } else {
+                leave = leave.min(q / p);
+            }
+        }
+        if enter > leave {
+            return None;
+        }
+
+        let at = |t: f64| {
+            let x = (from.0 as f64 + t * dx).round() as i64;
+            let y = (from.1 as f64 + t * dy).round() as i64;
+            (x.clamp(min_x, max_x), y.clamp(min_y, max_y))
+        };
+        // unclipped ends stay exact so strokes inside the map are walked as before
+        let start = if enter > 0.0 { at(enter) } else { from };
+        let end = if leave < 1.0 { at(leave) } else { to };
+        Some((start, end))
+    }
+
+    /// Tiles of the rectangle with corners (x0, y0) and (x1, y1), both included
+    fn rect_tiles(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<usize> {
+        let (min_x, max_x) = (x0.min(x1).max(0), x0.max(x1).min(self.width as i32 - 1));
+        let (min_y, max_y) = (y0.min(y1).max(0), y0.max(y1).min(self.height as i32 - 1));
+
+        (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| y as usize * self.width + x as usize))
+            .collect()
+    }
+
+    /// Tiles whose centre is inside the polygon (even-odd rule) plus its 1 tile outline.
+    /// `points` holds x0, y0, x1, y1, ... in tile coordinates.
+    fn polygon_tiles(&self, points: &[i32]) -> Result<Vec<usize>, String> {
+        if !points.len().is_multiple_of(2) || points.len() < 6 {
+            return Err(format!("a polygon needs at least 3 points as x, y pairs, got {} numbers", points.len()));
+        }
+        let vertices: Vec<(i32, i32)> = points.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+
+        let mut tiles = Vec::new();
+        for (i, &(x0, y0)) in vertices.iter().enumerate() {
+            let (x1, y1) = vertices[(i + 1) % vertices.len()];
+            tiles.extend(self.line_tiles(x0, y0, x1, y1, 1));
+        }
+
+        let min_y = vertices.iter().map(|v| v.1).min().unwrap().max(0);
+        let max_y = vertices.iter().map(|v| v.1).max().unwrap().min(self.height as i32 - 1);
+        let mut crossings = Vec::new();
+        for y in min_y..=max_y {
+            // vertices sit on tile centres, so the scanline through this row is y itself
+            let scan = y as f64;
+            crossings.clear();
+            for (i, &(x0, y0)) in vertices.iter().enumerate() {
+                let (x1, y1) = vertices[(i + 1) % vertices.len()];
+                // half-open so a vertex on the scanline counts once
+                if (y0 as f64 <= scan) != (y1 as f64 <= scan) {
+                    let t = (scan - y0 as f64) / (y1 as f64 - y0 as f64);
+                    crossings.push(x0 as f64 + t * (x1 as f64 - x0 as f64));
+                }
+            }
+            crossings.sort_unstable_by(|a, b| a.total_cmp(b));
+
+            for pair in crossings.chunks_exact(2) {
+                let first = (pair[0].ceil() as i32).max(0);
+                let last = (pair[1].floor() as i32).min(self.width as i32 - 1);
+                for x in first..=last {
+                    tiles.push(y as usize * self.width + x as usize);
+                }
+            }
+        }
+
+        tiles.sort_unstable();
+        tiles.dedup();
+        Ok(tiles)
+    }
+
+    fn mark_tiles(&mut self, layer: Layer, tiles: &[usize], pad: usize) {
+        let (Some(&first), Some(&last)) = (tiles.first(), tiles.last()) else { return };
+        let (min_x, max_x) = tiles.iter().fold((usize::MAX, 0), |(lo, hi), &i| (lo.min(i % self.width), hi.max(i % self.width)));
+        self.dirty.mark(layer, min_x, first / self.width, max_x, last / self.width, pad);
+    }
+
+    pub(crate) fn draw_terrain(&mut self, tiles: &[usize], terrain: Terrain) -> u32 {
+        let color = terrain.get_color();
+
+        for &index in tiles {
+            self.tiles.set(index, terrain);
+            if let Some(pixel) = self.terrain_buffer.get_mut(index) {
+                *pixel = color;
+            }
+        }
+        self.mark_tiles(Layer::Terrain, tiles, 0);
+        // coastline outlines of the neighbours change too
+        self.mark_tiles(Layer::Borders, tiles, 1);
+
+        tiles.len() as u32
+    }
+
+    // Resources only go on liveable tiles, like paint_resource_brush
+    pub(crate) fn draw_resource(&mut self, tiles: &[usize], resource: Resource) -> u32 {
+        let color = resource.get_color();
+
+        let mut painted = 0;
+        for &index in tiles {
+            if !self.tiles[index].is_liveable() { continue; }
+
+            self.resources.set(index, resource);
+            if let Some(pixel) = self.resource_buffer.get_mut(index) {
+                *pixel = color;
+            }
+            painted += 1;
+        }
+        self.mark_tiles(Layer::Resources, tiles, 0);
+
+        painted
+    }
+}
+
+// Shapes for the editor, each returns the number of tiles painted
+#[wasm_bindgen]
+impl World {
+    /// The terrain brush dragged along a straight line, without the gaps of sampled mouse moves
+    pub fn paint_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, diameter: i32, terrain_val: char) -> Result<u32, String> {
+        let tiles = self.line_tiles(x0, y0, x1, y1, diameter);
+        Ok(self.draw_terrain(&tiles, parse_terrain(terrain_val)?))
+    }
+
+    /// Filled rectangle, both corners included
+    pub fn paint_rect(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, terrain_val: char) -> Result<u32, String> {
+        let tiles = self.rect_tiles(x0, y0, x1, y1);
+        Ok(self.draw_terrain(&tiles, parse_terrain(terrain_val)?))
+    }
+
+    /// Filled polygon, `points` as x0, y0, x1, y1, ... The outline is included.
+    pub fn paint_polygon(&mut self, points: Vec<i32>, terrain_val: char) -> Result<u32, String> {
+        let tiles = self.polygon_tiles(&points)?;
+        Ok(self.draw_terrain(&tiles, parse_terrain(terrain_val)?))
+    }
+
+    pub fn paint_resource_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, diameter: i32, resource_val: char) -> Result<u32, String> {
+        let tiles = self.line_tiles(x0, y0, x1, y1, diameter);
+        Ok(self.draw_resource(&tiles, parse_resource(resource_val)?))
+    }
+
+    pub fn paint_resource_rect(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, resource_val: char) -> Result<u32, String> {
+        let tiles = self.rect_tiles(x0, y0, x1, y1);
+        Ok(self.draw_resource(&tiles, parse_resource(resource_val)?))
+    }
+
+    pub fn paint_resource_polygon(&mut self, points: Vec<i32>, resource_val: char) -> Result<u32, String> {
+        let tiles = self.polygon_tiles(&points)?;
+        Ok(self.draw_resource(&tiles, parse_resource(resource_val)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plains(width: usize, height: usize) -> World {
+        World::new(&format!("{}\n", "P".repeat(width)).repeat(height), None)
+    }
+
+    fn row(y: usize, xs: std::ops::RangeInclusive<usize>) -> Vec<usize> {
+        xs.map(|x| y * 10 + x).collect()
+    }
+
+    #[test]
+    fn segments_are_clipped_to_the_map_and_margin() {
+        let world = plains(10, 6);
+        assert_eq!(world.clip_segment((-10, 3), (20, 3), 0), Some(((0, 3), (9, 3))));
+        assert_eq!(world.clip_segment((20, 3), (-10, 3), 2), Some(((11, 3), (-2, 3))));
+        assert_eq!(world.clip_segment((-1, 0), (-1, 5), 0), None);
+        assert_eq!(world.clip_segment((-1, 0), (-1, 5), 1), Some(((-1, 0), (-1, 5))));
+        // ends inside the map are kept as they are
+        assert_eq!(world.clip_segment((2, 1), (7, 4), 0), Some(((2, 1), (7, 4))));
+        assert_eq!(world.clip_segment((-20, -20), (-5, 30), 3), None);
+    }
+
+    #[test]
+    fn lines_from_off_the_map_paint_only_what_they_cross() {
+        let world = plains(10, 6);
+        assert_eq!(world.line_tiles(-5, 2, 14, 2, 1), row(2, 0..=9));
+        assert_eq!(world.line_tiles(3, -100, 3, 100, 1), (0..6).map(|y| y * 10 + 3).collect::<Vec<_>>());
+        assert!(world.line_tiles(-5, -5, 20, -5, 1).is_empty());
+
+        // a radius 2 circle two rows above the map still reaches row 0
+        assert_eq!(world.line_tiles(-50, -2, 50, -2, 5), row(0, 0..=9));
+        assert!(world.line_tiles(-50, -3, 50, -3, 5).is_empty());
+    }
+
+    #[test]
+    fn extreme_coordinates_walk_only_the_clipped_part() {
+        let world = plains(10, 6);
+        let diagonal: Vec<usize> = (0..6).map(|i| i * 10 + i).collect();
+        assert_eq!(world.line_tiles(i32::MIN, i32::MIN, i32::MAX, i32::MAX, 1), diagonal);
+        assert_eq!(world.line_tiles(i32::MAX, 0, i32::MIN, 0, 1), row(0, 0..=9));
+        assert!(world.line_tiles(i32::MIN, i32::MAX, i32::MAX, i32::MAX, 3).is_empty());
+    }
+
+    #[test]
+    fn paint_line_writes_the_clipped_tiles() {
+        let mut world = plains(10, 6);
+        assert_eq!(world.paint_line(-3, 5, 30, 5, 1, 'w'), Ok(10));
+        assert_eq!(world.export_map_to_string(false).lines().last(), Some("WWWWWWWWWW"));
+        assert!(world.paint_line(0, 0, 1, 1, 1, 'x').is_err());
+        // resources skip the water just painted
+        assert_eq!(world.paint_resource_line(-3, 5, 30, 5, 1, 'g'), Ok(0));
+        assert_eq!(world.paint_resource_line(-3, 4, 30, 4, 1, 'g'), Ok(10));
+    }
+}
@@ -0,0 +1,198 @@
+use wasm_bindgen::prelude::*;
+
+use crate::dirty::Layer;
+use crate::{Resource, Terrain, World};
+
+// Horizontal run of filled tiles, (y, first x, last x)
+type Span = (usize, usize, usize);
+
+impl World {
+    /// Scanline flood fill over the tiles whose terrain matches the one at (x, y).
+    /// `connectivity` is 4 or 8, `water_tolerant` lets Water and River count as one terrain.
+    fn flood(&self, x: usize, y: usize, connectivity: u8, water_tolerant: bool) -> Result<Vec<Span>, String> {
+        if x >= self.width || y >= self.height {
+            return Err(format!("({}, {}) is outside the {}x{} map", x, y, self.width, self.height));
+        }
+        let diagonal = match connectivity {
+            4 => 0,
+            8 => 1,
+            other => return Err(format!("connectivity must be 4 or 8, got {}", other)),
+        };
+
+        let width = self.width;
+        let target = self.tiles[y * width + x];
+        let matches = |terrain: Terrain| {
+            terrain == target || (water_tolerant && terrain.is_watery() && target.is_watery())
+        };
+
+        let mut visited = vec![false; width * self.height];
+        let mut spans = Vec::new();
+        let mut stack = vec![(x, y)];
+
+        while let Some((x, y)) = stack.pop() {
+            let row = y * width;
+            if visited[row + x] { continue; }
+
+            let mut first = x;
+            while first > 0 && !visited[row + first - 1] && matches(self.tiles[row + first - 1]) {
+                first -= 1;
+            }
+            let mut last = x;
+            while last + 1 < width && !visited[row + last + 1] && matches(self.tiles[row + last + 1]) {
+                last += 1;
+            }
+            visited[row + first..=row + last].fill(true);
+            spans.push((y, first, last));
+
+            // one seed per run of matching tiles in the rows above and below
+            let (from, to) = (first.saturating_sub(diagonal), (last + diagonal).min(width - 1));
+            let neighbours = [y.checked_sub(1), (y + 1 < self.height).then_some(y + 1)];
+            for ny in neighbours.into_iter().flatten() {
+                let mut in_run = false;
+                for nx in from..=to {
+                    let index = ny * width + nx;
+                    let open = !visited[index] && matches(self.tiles[index]);
+                    if open && !in_run {
+                        stack.push((nx, ny));
+                    }
+                    in_run = open;
+                }
+            }
+        }
+
+        Ok(spans)
+    }
+
+    fn mark_spans(&mut self, layer: Layer, spans: &[Span], pad: usize) {
+        for &(y, first, last) in spans {
+            self.dirty.mark(layer, first, y, last, y, pad);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Bucket fill: the region of the terrain under (x, y) becomes `terrain_val`.
+    /// Returns the number of tiles filled.
+    pub fn fill_terrain(&mut self, x: usize, y: usize, terrain_val: char, connectivity: u8, water_tolerant: bool) -> Result<u32, String> {
+        let terrain = Terrain::try_from_char(terrain_val.to_ascii_uppercase())
+            .ok_or_else(|| format!("unknown terrain character `{}`", terrain_val))?;
+        let spans = self.flood(x, y, connectivity, water_tolerant)?;
+        let color = terrain.get_color();
+
+        let mut filled = 0;
+        for &(y, first, last) in &spans {
+            for index in y * self.width + first..=y * self.width + last {
+                self.tiles.set(index, terrain);
+                if let Some(pixel) = self.terrain_buffer.get_mut(index) {
+                    *pixel = color;
+                }
+            }
+            filled += (last - first + 1) as u32;
+        }
+
+        self.mark_spans(Layer::Terrain, &spans, 0);
+        // coastline outlines of the neighbours change too
+        self.mark_spans(Layer::Borders, &spans, 1);
+
+        Ok(filled)
+    }
+
+    /// Puts `resource_val` on every liveable tile of the terrain region under (x, y),
+    /// matched like `fill_terrain`. Returns the number of tiles changed.
+    pub fn fill_resource(&mut self, x: usize, y: usize, resource_val: char, connectivity: u8, water_tolerant: bool) -> Result<u32, String> {
+        let resource = Resource::try_from_char(resource_val)
+            .ok_or_else(|| format!("unknown resource character `{}`", resource_val))?;
+        let spans = self.flood(x, y, connectivity, water_tolerant)?;
+        let color = resource.get_color();
+
+        let mut filled = 0;
+        for &(y, first, last) in &spans {
+            for index in y * self.width + first..=y * self.width + last {
+                if !self.tiles[index].is_liveable() { continue; }
+
+                self.resources.set(index, resource);
+                if let Some(pixel) = self.resource_buffer.get_mut(index) {
+                    *pixel = color;
+                }
+                filled += 1;
+            }
+        }
+
+        self.mark_spans(Layer::Resources, &spans, 0);
+
+        Ok(filled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plain diamond touching itself only at corners, a lake with a river mouth in the corner
+    const MAP: &str = "\
+PWWWWWWW
+WPWWWWRR
+WWPWWWWW
+WPWPWWPP
+PWWWWWPP
+";
+
+    fn rows(world: &World) -> Vec<String> {
+        world.export_map_to_string(false).lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn diagonal_neighbours_join_only_with_8_connectivity() {
+        let mut world = World::new(MAP, None);
+        assert_eq!(world.fill_terrain(0, 0, 'd', 4, false), Ok(1));
+        assert_eq!(rows(&world)[..2], ["DWWWWWWW", "WPWWWWRR"]);
+
+        let mut world = World::new(MAP, None);
+        assert_eq!(world.fill_terrain(0, 0, 'D', 8, false), Ok(6));
+        assert_eq!(rows(&world), [
+            "DWWWWWWW",
+            "WDWWWWRR",
+            "WWDWWWWW",
+            "WDWDWWPP",
+            "DWWWWWPP",
+        ]);
+    }
+
+    #[test]
+    fn water_tolerance_crosses_into_rivers() {
+        let mut world = World::new(MAP, None);
+        // the water left of the diamond is cut off without diagonals
+        assert_eq!(world.fill_terrain(7, 0, 'I', 4, false), Ok(24));
+        assert_eq!(rows(&world)[..3], ["PIIIIIII", "WPIIIIRR", "WWPIIIII"]);
+
+        let mut world = World::new(MAP, None);
+        assert_eq!(world.fill_terrain(7, 0, 'I', 4, true), Ok(26));
+        assert_eq!(rows(&world)[1], "WPIIIIII");
+
+        let mut world = World::new(MAP, None);
+        assert_eq!(world.fill_terrain(7, 0, 'I', 8, true), Ok(30));
+        assert_eq!(rows(&world)[..3], ["PIIIIIII", "IPIIIIII", "IIPIIIII"]);
+    }
+
+    #[test]
+    fn resources_only_land_on_liveable_tiles() {
+        let mut world = World::new(MAP, None);
+        // of the water and rivers only the two river tiles are liveable
+        assert_eq!(world.fill_resource(7, 0, 'f', 8, true), Ok(2));
+        assert_eq!(world.fill_resource(6, 3, 'w', 4, false), Ok(4));
+        let resources: Vec<String> = world.export_resource_to_string(false).lines().map(str::to_string).collect();
+        assert_eq!(resources[1..], ["......ff", "........", "......ww", "......ww"]);
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        let mut world = World::new(MAP, None);
+        assert!(world.fill_terrain(8, 0, 'P', 4, false).is_err());
+        assert!(world.fill_terrain(0, 5, 'P', 4, false).is_err());
+        assert!(world.fill_terrain(0, 0, 'P', 6, false).is_err());
+        assert!(world.fill_terrain(0, 0, 'x', 4, false).is_err());
+        assert!(world.fill_resource(0, 0, 'x', 4, false).is_err());
+        assert_eq!(rows(&World::new(MAP, None)), rows(&world));
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::brush::mix;
+use crate::dirty::Layer;
+use crate::image::TERRAINS;
+use crate::{Terrain, World};
+
+const NEIGHBOURS_8: [(i64, i64); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+const NEIGHBOURS_4: [(i64, i64); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+// Inclusive tile box a filter works on
+#[derive(Clone, Copy)]
+struct Area {
+    min_x: usize,
+    min_y: usize,
+    max_x: usize,
+    max_y: usize,
+}
+
+impl Area {
+    fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+}
+
+// Most common terrain among `terrains`, None when there are none
+pub(crate) fn most_common(terrains: impl Iterator<Item = Terrain>) -> Option<(Terrain, u32)> {
+    let mut counts = [0u32; TERRAINS.len()];
+    for terrain in terrains {
+        counts[terrain as usize] += 1;
+    }
+    // the first terrain in enum order wins ties
+    counts.iter().enumerate()
+        .filter(|(_, count)| **count > 0)
+        .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(&a.0)))
+        .map(|(i, &count)| (TERRAINS[i], count))
+}
+
+fn is_land(terrain: Terrain) -> bool {
+    terrain.is_liveable() && !terrain.is_watery()
+}
+
+// The part of the map a filter copies: its area plus the margin its rule looks around a
+// tile, clipped to the map. Inside the margin "in the window" is the same as "on the map".
+#[derive(Clone, Copy)]
+struct Window {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Window {
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y - self.top) * self.width + x - self.left
+    }
+
+    fn contains(&self, x: i64, y: i64) -> bool {
+        x >= self.left as i64 && y >= self.top as i64
+            && x < (self.left + self.width) as i64 && y < (self.top + self.height) as i64
+    }
+}
+
+// Terrain of the window during a filter pass, in map coordinates
+struct View<'a> {
+    tiles: &'a [Terrain],
+    window: Window,
+}
+
+impl View<'_> {
+    fn at(&self, x: usize, y: usize) -> Terrain {
+        self.tiles[self.window.index(x, y)]
+    }
+
+    // Terrain of the in-map neighbours of (x, y)
+    fn neighbours<'b>(&'b self, x: usize, y: usize, offsets: &'b [(i64, i64)]) -> impl Iterator<Item = Terrain> + 'b {
+        offsets.iter().filter_map(move |&(dx, dy)| {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+            self.window.contains(nx, ny).then(|| self.at(nx as usize, ny as usize))
+        })
+    }
+
+    // Terrain of the in-map tiles of the (2 * radius + 1) square around (x, y), itself included
+    fn square(&self, x: usize, y: usize, radius: usize) -> impl Iterator<Item = Terrain> + '_ {
+        let window = self.window;
+        let (min_x, max_x) = (x.saturating_sub(radius).max(window.left), x.saturating_add(radius).min(window.left + window.width - 1));
+        let (min_y, max_y) = (y.saturating_sub(radius).max(window.top), y.saturating_add(radius).min(window.top + window.height - 1));
+        (min_y..=max_y).flat_map(move |y| (min_x..=max_x).map(move |x| self.at(x, y)))
+    }
+}
+
+impl World {
+    fn area(&self, x: usize, y: usize, w: usize, h: usize) -> Option<Area> {
+        if w == 0 || h == 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(Area {
+            min_x: x,
+            min_y: y,
+            max_x: x.saturating_add(w - 1).min(self.width - 1),
+            max_y: y.saturating_add(h - 1).min(self.height - 1),
+        })
+    }
+
+    /// One pass of `rule` over the area, every tile computed from the terrain before the pass.
+    /// `rule(view, x, y)` returns the tile's new terrain, if it changes. Changes are indexed
+    /// in the window.
+    fn filter_pass<F>(&self, tiles: &[Terrain], window: Window, area: Area, rule: F) -> Vec<(usize, Terrain)>
+    where
+        F: Fn(&View, usize, usize) -> Option<Terrain> + Sync,
+    {
+        let view = View { tiles, window };
+        (area.min_y..=area.max_y)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                let (rule, view) = (&rule, &view);
+                (area.min_x..=area.max_x).filter_map(move |x| {
+                    let index = window.index(x, y);
+                    rule(view, x, y).filter(|&t| t != tiles[index]).map(|t| (index, t))
+                })
+            })
+            .collect()
+    }
+
+    /// Runs `passes` passes of `rule` and writes the result, returns the number of tiles changed.
+    /// `margin` is how far from a tile the rule looks, only the area and that margin are copied.
+    fn run_filter<F>(&mut self, area: Option<Area>, margin: usize, passes: u32, rule: F) -> u32
+    where
+        F: Fn(&View, usize, usize) -> Option<Terrain> + Sync,
+    {
+        let Some(area) = area else { return 0 };
+        let (left, top) = (area.min_x.saturating_sub(margin), area.min_y.saturating_sub(margin));
+        let window = Window {
+            left,
+            top,
+            width: area.max_x.saturating_add(margin).min(self.width - 1) - left + 1,
+            height: area.max_y.saturating_add(margin).min(self.height - 1) - top + 1,
+        };
+        let (map_width, grid) = (self.width, &self.tiles);
+        let original: Vec<Terrain> = (top..top + window.height)
+            .flat_map(|y| (left..left + window.width).map(move |x| grid[y * map_width + x]))
+            .collect();
+        let mut tiles = original.clone();
+
+        for _ in 0..passes {
+            let changes = self.filter_pass(&tiles, window, area, &rule);
+            if changes.is_empty() { break; }
+            for (index, terrain) in changes {
+                tiles[index] = terrain;
+            }
+        }
+
+        self.write_terrain(&original, &tiles, window, area)
+    }
+
+    // Copies the tiles of the area that differ from `original` into the world, both
+    // indexed in the window
+    fn write_terrain(&mut self, original: &[Terrain], tiles: &[Terrain], window: Window, area: Area) -> u32 {
+        let mut changed = 0;
+        for y in area.min_y..=area.max_y {
+            for x in area.min_x..=area.max_x {
+                let (local, index) = (window.index(x, y), y * self.width + x);
+                if tiles[local] == original[local] { continue; }
+
+                self.tiles.set(index, tiles[local]);
+                if let Some(pixel) = self.terrain_buffer.get_mut(index) {
+                    *pixel = tiles[local].get_color();
+                }
+                changed += 1;
+            }
+        }
+
+        if changed > 0 {
+            self.dirty.mark(Layer::Terrain, area.min_x, area.min_y, area.max_x, area.max_y, 0);
+            // coastline outlines of the neighbours change too
+            self.dirty.mark(Layer::Borders, area.min_x, area.min_y, area.max_x, area.max_y, 1);
+        }
+        changed
+    }
+}
+
+/// Terrain filters for the editor. Each works on the w x h rectangle at (x, y), clipped to
+/// the map (0, 0, width, height for the whole map), computes every pass from the previous
+/// one in parallel and returns the number of tiles changed.
+#[wasm_bindgen]
+impl World {
+    /// Mode filter: a tile takes the most common terrain of the (2 * radius + 1) square
+    /// around it when that terrain outnumbers its own. Removes single-tile specks.
+    /// A radius past the map's size covers the whole map, it is clamped to that.
+    pub fn majority_filter(&mut self, x: usize, y: usize, w: usize, h: usize, radius: u32, passes: u32) -> u32 {
+        let area = self.area(x, y, w, h);
+        let radius = (radius.max(1) as usize).min(self.width.max(self.height));
+
+        self.run_filter(area, radius, passes, |view, x, y| {
+            let own = view.at(x, y);
+            let (best, count) = most_common(view.square(x, y, radius))?;
+            let own_count = view.square(x, y, radius).filter(|&t| t == own).count() as u32;
+            (count > own_count).then_some(best)
+        })
+    }
+
+    /// Rounds coastlines: land with 5 or more watery neighbours (of 8) becomes Water, Water
+    /// with 5 or more land neighbours becomes their most common land terrain. Rivers are kept.
+    pub fn smooth_coastline(&mut self, x: usize, y: usize, w: usize, h: usize, passes: u32) -> u32 {
+        let area = self.area(x, y, w, h);
+
+        self.run_filter(area, 1, passes, |view, x, y| {
+            let own = view.at(x, y);
+            if is_land(own) {
+                let watery = view.neighbours(x, y, &NEIGHBOURS_8).filter(|t| t.is_watery()).count();
+                (watery >= 5).then_some(Terrain::Water)
+            } else if own == Terrain::Water {
+                let land = view.neighbours(x, y, &NEIGHBOURS_8).filter(|&t| is_land(t));
+                most_common(land).filter(|&(_, count)| count >= 5).map(|(terrain, _)| terrain)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Dilation: every tile next to (4 neighbours) `terrain_val` becomes it, `steps` times
+    pub fn grow_terrain(&mut self, x: usize, y: usize, w: usize, h: usize, terrain_val: char, steps: u32) -> Result<u32, String> {
+        let terrain = Terrain::try_from_char(terrain_val.to_ascii_uppercase())
+            .ok_or_else(|| format!("unknown terrain character `{}`", terrain_val))?;
+        let area = self.area(x, y, w, h);
+
+        Ok(self.run_filter(area, 1, steps, |view, x, y| {
+            view.neighbours(x, y, &NEIGHBOURS_4).any(|t| t == terrain).then_some(terrain)
+        }))
+    }
+
+    /// Erosion: every `terrain_val` tile touching (4 neighbours) another terrain takes the
+    /// most common of its other neighbours, `steps` times
+    pub fn shrink_terrain(&mut self, x: usize, y: usize, w: usize, h: usize, terrain_val: char, steps: u32) -> Result<u32, String> {
+        let terrain = Terrain::try_from_char(terrain_val.to_ascii_uppercase())
+            .ok_or_else(|| format!("unknown terrain character `{}`", terrain_val))?;
+        let area = self.area(x, y, w, h);
+
+        Ok(self.run_filter(area, 1, steps, |view, x, y| {
+            if view.at(x, y) != terrain { return None; }
+            most_common(view.neighbours(x, y, &NEIGHBOURS_4).filter(|&t| t != terrain)).map(|(t, _)| t)
+        }))
+    }
+
+    /// Hydraulic erosion: `drops` Mountain tiles of the area, picked from `seed`, each send
+    /// water downhill to the sea, carving River along the way. Height is the distance to the
+    /// nearest watery tile, ties between equally low neighbours are broken at random.
+    /// A river stops where it joins water or leaves the area.
+    pub fn erode_rivers(&mut self, x: usize, y: usize, w: usize, h: usize, drops: u32, seed: u64) -> u32 {
+        let Some(area) = self.area(x, y, w, h) else { return 0 };
+        let (width, height) = (self.width, self.height);
+        let original = self.tiles.to_vec();
+
+        // multi-source BFS from every watery tile
+        let mut level = vec![u32::MAX; original.len()];
+        let mut queue: VecDeque<usize> = (0..original.len()).filter(|&i| original[i].is_watery()).collect();
+        for &index in &queue {
+            level[index] = 0;
+        }
+        while let Some(index) = queue.pop_front() {
+            let (x, y) = (index % width, index / width);
+            for (dx, dy) in NEIGHBOURS_4 {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 { continue; }
+                let next = ny as usize * width + nx as usize;
+                if level[next] == u32::MAX {
+                    level[next] = level[index] + 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        let mut sources: Vec<usize> = (area.min_y..=area.max_y)
+            .flat_map(|y| (area.min_x..=area.max_x).map(move |x| y * width + x))
+            .filter(|&i| original[i] == Terrain::Mountain && level[i] != u32::MAX)
+            .collect();
+        sources.sort_unstable_by_key(|&i| mix(seed ^ i as u64));
+        sources.truncate(drops as usize);
+
+        let paths: Vec<Vec<usize>> = sources.into_par_iter()
+            .map(|source| {
+                let mut path = Vec::new();
+                let mut index = source;
+                let mut step = 0u64;
+                while level[index] > 0 && area.contains(index % width, index / width) {
+                    path.push(index);
+                    let (x, y) = (index % width, index / width);
+                    step += 1;
+                    // the BFS guarantees a neighbour one level lower
+                    index = NEIGHBOURS_4.iter()
+                        .filter_map(|&(dx, dy)| {
+                            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                            (nx >= 0 && ny >= 0 && nx < width as i64 && ny < height as i64).then(|| ny as usize * width + nx as usize)
+                        })
+                        .filter(|&next| level[next] < level[index])
+                        .min_by_key(|&next| mix(seed ^ mix(source as u64 ^ (step << 32)) ^ next as u64))
+                        .expect("a lower neighbour exists");
+                }
+                path
+            })
+            .collect();
+
+        let mut tiles = original.clone();
+        for index in paths.into_iter().flatten() {
+            tiles[index] = Terrain::River;
+        }
+        self.write_terrain(&original, &tiles, Window { left: 0, top: 0, width, height }, area)
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::grid::Grid;
+
+/// Owned tiles with at least one unowned neighbour, per empire.
+///
+/// Kept up to date by `World::set_owner`, so `auto_grow` can find its starting
+/// points without scanning the whole map. A tile's membership only depends on the
+/// owners of itself and its four neighbours.
+#[derive(Default)]
+pub struct Frontier {
+    tiles: HashMap<u32, HashSet<usize>>,
+}
+
+impl Frontier {
+    /// Frontier of a whole owner grid, for when the grid is replaced rather than edited
+    pub fn rebuild(owners: &Grid<u32>, width: usize, height: usize) -> Frontier {
+        let mut frontier = Frontier::default();
+        for index in owners.indices_where(|owner| owner != 0) {
+            frontier.refresh(owners, width, height, index);
+        }
+        frontier
+    }
+
+    /// Updates the tile and its neighbours after `index` went from `previous` to its current owner
+    pub fn owner_changed(&mut self, owners: &Grid<u32>, width: usize, height: usize, index: usize, previous: u32) {
+        if previous != 0 {
+            self.remove(previous, index);
+        }
+
+        self.refresh(owners, width, height, index);
+        let (x, y) = (index % width, index / width);
+        if x > 0 { self.refresh(owners, width, height, index - 1); }
+        if x + 1 < width { self.refresh(owners, width, height, index + 1); }
+        if y > 0 { self.refresh(owners, width, height, index - width); }
+        if y + 1 < height { self.refresh(owners, width, height, index + width); }
+    }
+
+    /// (empire, tile) for every frontier tile
+    pub fn entries(&self) -> Vec<(u32, usize)> {
+        self.tiles.iter()
+            .flat_map(|(&empire_id, tiles)| tiles.iter().map(move |&index| (empire_id, index)))
+            .collect()
+    }
+
+    fn refresh(&mut self, owners: &Grid<u32>, width: usize, height: usize, index: usize) {
+        let owner = owners[index];
+        if owner == 0 {
+            return;
+        }
+
+        let (x, y) = (index % width, index / width);
+        let open = (x > 0 && owners[index - 1] == 0)
+            || (x + 1 < width && owners[index + 1] == 0)
+            || (y > 0 && owners[index - width] == 0)
+            || (y + 1 < height && owners[index + width] == 0);
+
+        if open {
+            self.tiles.entry(owner).or_default().insert(index);
+        } else {
+            self.remove(owner, index);
+        }
+    }
+
+    fn remove(&mut self, empire_id: u32, index: usize) {
+        if let Some(tiles) = self.tiles.get_mut(&empire_id) {
+            tiles.remove(&index);
+            if tiles.is_empty() {
+                self.tiles.remove(&empire_id);
+            }
+        }
+    }
+}
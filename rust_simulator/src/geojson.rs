@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::{World, owner_color};
+
+// Grid corner (x, y), tile (x, y) covers [x, x + 1] x [y, y + 1]
+type Vertex = (i64, i64);
+
+// A polygon: outer ring first, then its holes. Rings are closed (first vertex repeated).
+type Polygon = Vec<Vec<Vertex>>;
+
+/// Labels 4-connected regions of equal non-zero owner, returns the tiles of each region
+fn regions(owners: &[u32], width: usize, height: usize) -> Vec<Vec<usize>> {
+    let mut label = vec![usize::MAX; owners.len()];
+    let mut regions = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..owners.len() {
+        if owners[start] == 0 || label[start] != usize::MAX { continue; }
+
+        let id = regions.len();
+        let owner = owners[start];
+        let mut tiles = Vec::new();
+        label[start] = id;
+        stack.push(start);
+
+        while let Some(index) = stack.pop() {
+            tiles.push(index);
+            let (x, y) = (index % width, index / width);
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width),
+                (y + 1 < height).then(|| index + width),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if owners[n] == owner && label[n] == usize::MAX {
+                    label[n] = id;
+                    stack.push(n);
+                }
+            }
+        }
+        regions.push(tiles);
+    }
+
+    regions
+}
+
+/// Traces the outline and holes of one region.
+///
+/// Every tile side facing a tile outside the region becomes a directed edge with the region
+/// on its right (y points down), so the outline runs clockwise on screen and holes the other
+/// way. Where two tiles of the region only touch at a corner (they are joined elsewhere) the
+/// walk turns left, across that corner, so a hole touching the outline or another hole there
+/// stays a ring of its own. GeoJSON allows rings to touch at a point but not to cross into
+/// themselves.
+fn trace(tiles: &[usize], owners: &[u32], width: usize, height: usize) -> Polygon {
+    let owner = owners[tiles[0]];
+    let inside = |x: i64, y: i64| {
+        x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height
+            && owners[y as usize * width + x as usize] == owner
+    };
+
+    // outgoing edges per start corner
+    let mut edges: HashMap<Vertex, Vec<Vertex>> = HashMap::new();
+    let mut edge_count = 0;
+    for &index in tiles {
+        let (x, y) = ((index % width) as i64, (index / width) as i64);
+        let sides = [
+            (!inside(x, y - 1), (x, y), (x + 1, y)),
+            (!inside(x + 1, y), (x + 1, y), (x + 1, y + 1)),
+            (!inside(x, y + 1), (x + 1, y + 1), (x, y + 1)),
+            (!inside(x - 1, y), (x, y + 1), (x, y)),
+        ];
+        for (open, from, to) in sides {
+            if open {
+                edges.entry(from).or_default().push(to);
+                edge_count += 1;
+            }
+        }
+    }
+
+    let mut rings = Vec::new();
+    let mut starts: Vec<Vertex> = edges.keys().copied().collect();
+    starts.sort_unstable_by_key(|&(x, y)| (y, x));
+
+    for start in starts {
+        while edge_count > 0 {
+            let Some(first) = edges.get_mut(&start).and_then(|out| out.pop()) else { break };
+            edge_count -= 1;
+
+            let mut ring = vec![start];
+            let (mut from, mut at) = (start, first);
+            while at != start {
+                let heading = (at.0 - from.0, at.1 - from.1);
+                // left turn, straight, right turn (screen coordinates)
+                let preference = [(heading.1, -heading.0), heading, (-heading.1, heading.0)];
+                let out = edges.get_mut(&at).expect("region outline is closed");
+                let pick = preference.iter()
+                    .find_map(|d| out.iter().position(|&to| to == (at.0 + d.0, at.1 + d.1)))
+                    .expect("region outline is closed");
+                let next = out.swap_remove(pick);
+                edge_count -= 1;
+
+                // only corners are kept
+                if (next.0 - at.0, next.1 - at.1) != heading {
+                    ring.push(at);
+                }
+                from = at;
+                at = next;
+            }
+
+            // the start may sit in the middle of a straight side
+            if ring.len() > 2 {
+                let (a, b, c) = (ring[ring.len() - 1], ring[0], ring[1]);
+                if (b.0 - a.0) * (c.1 - b.1) == (b.1 - a.1) * (c.0 - b.0) {
+                    ring.remove(0);
+                }
+            }
+            ring.push(ring[0]);
+            rings.push(ring);
+        }
+    }
+
+    // the outline is the only clockwise (positive area with y down) ring
+    let outer = rings.iter()
+        .position(|ring| signed_area(ring.iter().map(|&(x, y)| (x as f64, y as f64))) > 0.0)
+        .expect("region has an outline");
+    let outline = rings.swap_remove(outer);
+    rings.insert(0, outline);
+    rings
+}
+
+// Shoelace sum, positive for counterclockwise rings in a y-up frame
+fn signed_area(ring: impl Iterator<Item = (f64, f64)> + Clone) -> f64 {
+    ring.clone().zip(ring.skip(1)).map(|((x0, y0), (x1, y1))| x0 * y1 - x1 * y0).sum::<f64>() / 2.0
+}
+
+// Empire colors are 0xAABBGGRR
+fn css_color(color: u32) -> String {
+    format!("#{:02X}{:02X}{:02X}", color & 0xFF, (color >> 8) & 0xFF, (color >> 16) & 0xFF)
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Empire territories as a GeoJSON FeatureCollection, one MultiPolygon feature per empire
+    /// (one polygon per connected region, holes included) with `id`, `color`, `tiles` and
+    /// `resource_value` properties.
+    ///
+    /// Coordinates are tile corners unless `transform` is given, as the 6 numbers of a GDAL
+    /// geotransform: `lon = t[0] + x * t[1] + y * t[2]`, `lat = t[3] + x * t[4] + y * t[5]`.
+    /// For world.txt (720x360) that is `[-180, 0.5, 0, 90, 0, -0.5]`.
+    pub fn export_geojson(&self, transform: Option<Vec<f64>>) -> Result<String, String> {
+        let transform = match transform {
+            None => [0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            Some(t) if t.len() == 6 && t.iter().all(|v| v.is_finite()) => [t[0], t[1], t[2], t[3], t[4], t[5]],
+            Some(t) => return Err(format!("transform needs 6 finite numbers, got {:?}", t)),
+        };
+        let project = |(x, y): Vertex| {
+            let (x, y) = (x as f64, y as f64);
+            (transform[0] + x * transform[1] + y * transform[2], transform[3] + x * transform[4] + y * transform[5])
+        };
+
+        let owners = self.owners.to_vec();
+        let (width, height) = (self.width, self.height);
+
+        let traced: Vec<(u32, usize, u64, Polygon)> = regions(&owners, width, height)
+            .into_par_iter()
+            .map(|tiles| {
+                let value = tiles.iter().map(|&i| self.resources[i].get_value() as u64).sum();
+                (owners[tiles[0]], tiles.len(), value, trace(&tiles, &owners, width, height))
+            })
+            .collect();
+
+        // owner -> (tiles, resource value, polygons)
+        let mut empires: BTreeMap<u32, (usize, u64, Vec<Polygon>)> = BTreeMap::new();
+        for (owner, tiles, value, polygon) in traced {
+            let entry = empires.entry(owner).or_default();
+            entry.0 += tiles;
+            entry.1 += value;
+            entry.2.push(polygon);
+        }
+
+        let mut out = String::from("{\"type\":\"FeatureCollection\",\"features\":[");
+        for (i, (owner, (tiles, value, polygons))) in empires.into_iter().enumerate() {
+            if i > 0 { out.push(','); }
+            let color = css_color(owner_color(&self.empires, None, owner));
+            let _ = write!(out,
+                "{{\"type\":\"Feature\",\"properties\":{{\"id\":{},\"color\":\"{}\",\"tiles\":{},\"resource_value\":{}}},\"geometry\":{{\"type\":\"MultiPolygon\",\"coordinates\":[",
+                owner, color, tiles, value);
+
+            for (p, polygon) in polygons.iter().enumerate() {
+                if p > 0 { out.push(','); }
+                out.push('[');
+                for (r, ring) in polygon.iter().enumerate() {
+                    if r > 0 { out.push(','); }
+                    let mut points: Vec<(f64, f64)> = ring.iter().map(|&v| project(v)).collect();
+                    // RFC 7946: outlines counterclockwise, holes clockwise, after projection
+                    let counterclockwise = signed_area(points.iter().copied()) > 0.0;
+                    if counterclockwise != (r == 0) {
+                        points.reverse();
+                    }
+
+                    out.push('[');
+                    for (k, (x, y)) in points.iter().enumerate() {
+                        if k > 0 { out.push(','); }
+                        let _ = write!(out, "[{},{}]", x, y);
+                    }
+                    out.push(']');
+                }
+                out.push(']');
+            }
+            out.push_str("]}}");
+        }
+        out.push_str("]}");
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn traced(rows: &[&str]) -> Vec<Polygon> {
+        let (width, height) = (rows[0].len(), rows.len());
+        let owners: Vec<u32> = rows.iter().flat_map(|row| row.bytes().map(|b| (b - b'0') as u32)).collect();
+        regions(&owners, width, height).iter().map(|tiles| trace(tiles, &owners, width, height)).collect()
+    }
+
+    fn simple(ring: &[Vertex]) -> bool {
+        let mut corners = ring[..ring.len() - 1].to_vec();
+        corners.sort_unstable();
+        corners.dedup();
+        ring.first() == ring.last() && corners.len() == ring.len() - 1
+    }
+
+    #[test]
+    fn hole_touching_the_outline_at_a_corner_is_its_own_ring() {
+        let polygons = traced(&["1110", "1010", "1100"]);
+        assert_eq!(polygons.len(), 1);
+
+        let polygon = &polygons[0];
+        assert_eq!(polygon.len(), 2, "outline and hole as separate rings: {:?}", polygon);
+        assert!(polygon.iter().all(|ring| simple(ring)), "a ring touches itself: {:?}", polygon);
+        assert_eq!(polygon[0].len(), 7);
+
+        let mut hole = polygon[1][..4].to_vec();
+        hole.sort_unstable();
+        assert_eq!(hole, vec![(1, 1), (1, 2), (2, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn holes_touching_at_a_corner_stay_apart() {
+        let polygons = traced(&["1111", "1011", "1101", "1111"]);
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].len(), 3);
+        assert!(polygons[0].iter().all(|ring| simple(ring)));
+    }
+
+    #[test]
+    fn outline_runs_clockwise_and_holes_the_other_way() {
+        let polygon = &traced(&["111", "101", "111"])[0];
+        let area = |ring: &Vec<Vertex>| signed_area(ring.iter().map(|&(x, y)| (x as f64, y as f64)));
+        assert_eq!(area(&polygon[0]), 9.0);
+        assert_eq!(area(&polygon[1]), -1.0);
+    }
+}
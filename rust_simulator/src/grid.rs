@@ -0,0 +1,255 @@
+use std::ops::{Index, IndexMut};
+
+use rayon::prelude::*;
+
+// Chunks are CHUNK_SIZE x CHUNK_SIZE tiles, edge chunks are allocated full size too
+pub const CHUNK_SHIFT: usize = 6;
+pub const CHUNK_SIZE: usize = 1 << CHUNK_SHIFT;
+const CHUNK_MASK: usize = CHUNK_SIZE - 1;
+const CHUNK_AREA: usize = CHUNK_SIZE * CHUNK_SIZE;
+
+#[derive(Clone)]
+pub enum Chunk<T> {
+    // every tile of the chunk holds this value, nothing is allocated
+    Uniform(T),
+    Dense(Box<[T]>),
+}
+
+/// One value per map tile, indexed with the usual `y * width + x`.
+///
+/// `Flat` is a plain vector. `Chunked` splits the map in 64x64 chunks that are only
+/// allocated once a tile inside them differs from the rest, so open ocean or an
+/// untouched distance map cost one value per chunk.
+#[derive(Clone)]
+pub enum Grid<T> {
+    Flat(Vec<T>),
+    Chunked {
+        width: usize,
+        height: usize,
+        chunks_x: usize,
+        chunks: Vec<Chunk<T>>,
+    },
+}
+
+impl<T> Grid<T>
+where
+    T: Copy + PartialEq + Send + Sync,
+{
+    pub fn new(width: usize, height: usize, value: T, chunked: bool) -> Grid<T> {
+        if !chunked {
+            return Grid::Flat(vec![value; width * height]);
+        }
+
+        let chunks_x = width.div_ceil(CHUNK_SIZE);
+        let chunks_y = height.div_ceil(CHUNK_SIZE);
+        Grid::Chunked {
+            width,
+            height,
+            chunks_x,
+            chunks: vec![Chunk::Uniform(value); chunks_x * chunks_y],
+        }
+    }
+
+    pub fn from_vec(data: Vec<T>, width: usize, height: usize, chunked: bool) -> Grid<T> {
+        if !chunked || data.is_empty() {
+            return Grid::Flat(data);
+        }
+
+        let mut grid = Grid::new(width, height, data[0], true);
+        for (index, &value) in data.iter().enumerate() {
+            grid.set(index, value);
+        }
+        grid.compact();
+        grid
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Grid::Flat(data) => data.len(),
+            Grid::Chunked { width, height, .. } => width * height,
+        }
+    }
+
+    #[inline]
+    fn locate(width: usize, chunks_x: usize, index: usize) -> (usize, usize) {
+        let x = index % width;
+        let y = index / width;
+        let chunk = (y >> CHUNK_SHIFT) * chunks_x + (x >> CHUNK_SHIFT);
+        let local = ((y & CHUNK_MASK) << CHUNK_SHIFT) | (x & CHUNK_MASK);
+        (chunk, local)
+    }
+
+    /// Writes a value without allocating a chunk when it already holds that value everywhere
+    #[inline]
+    pub fn set(&mut self, index: usize, value: T) {
+        if let Grid::Chunked { width, chunks_x, chunks, .. } = self {
+            let (chunk, _) = Self::locate(*width, *chunks_x, index);
+            if let Chunk::Uniform(current) = chunks[chunk]
+                && current == value
+            {
+                return;
+            }
+        }
+        self[index] = value;
+    }
+
+    pub fn fill(&mut self, value: T) {
+        match self {
+            Grid::Flat(data) => data.fill(value),
+            Grid::Chunked { chunks, .. } => chunks.iter_mut().for_each(|c| *c = Chunk::Uniform(value)),
+        }
+    }
+
+    /// Frees dense chunks whose tiles all ended up with the same value
+    pub fn compact(&mut self) {
+        if let Grid::Chunked { chunks, .. } = self {
+            chunks.par_iter_mut().for_each(|chunk| {
+                if let Chunk::Dense(data) = chunk {
+                    let first = data[0];
+                    if data.iter().all(|&v| v == first) {
+                        *chunk = Chunk::Uniform(first);
+                    }
+                }
+            });
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        match self {
+            Grid::Flat(data) => data.clone(),
+            Grid::Chunked { .. } => (0..self.len()).map(|i| self[i]).collect(),
+        }
+    }
+
+    /// Indices (ascending) of every tile whose value matches `pred`
+    pub fn indices_where<P>(&self, pred: P) -> Vec<usize>
+    where
+        P: Fn(T) -> bool + Sync + Send,
+    {
+        match self {
+            Grid::Flat(data) => data.par_iter()
+                .enumerate()
+                .filter(|&(_, &v)| pred(v))
+                .map(|(index, _)| index)
+                .collect(),
+            Grid::Chunked { width, height, chunks_x, chunks } => {
+                let (width, height, chunks_x) = (*width, *height, *chunks_x);
+                let mut found: Vec<usize> = chunks.par_iter()
+                    .enumerate()
+                    .flat_map_iter(|(chunk_index, chunk)| {
+                        let x0 = (chunk_index % chunks_x) * CHUNK_SIZE;
+                        let y0 = (chunk_index / chunks_x) * CHUNK_SIZE;
+                        let mut hits = Vec::new();
+
+                        // a uniform chunk matches everywhere or nowhere
+                        if let Chunk::Uniform(v) = chunk
+                            && !pred(*v)
+                        {
+                            return hits;
+                        }
+
+                        for local in 0..CHUNK_AREA {
+                            let x = x0 + (local & CHUNK_MASK);
+                            let y = y0 + (local >> CHUNK_SHIFT);
+                            if x >= width || y >= height { continue; }
+
+                            let v = match chunk {
+                                Chunk::Uniform(v) => *v,
+                                Chunk::Dense(data) => data[local],
+                            };
+                            if pred(v) {
+                                hits.push(y * width + x);
+                            }
+                        }
+                        hits
+                    })
+                    .collect();
+                found.par_sort_unstable();
+                found
+            }
+        }
+    }
+
+    /// Number of allocated 64x64 chunks, 0 for the flat backend
+    pub fn dense_chunks(&self) -> usize {
+        match self {
+            Grid::Flat(_) => 0,
+            Grid::Chunked { chunks, .. } => chunks.iter().filter(|c| matches!(c, Chunk::Dense(_))).count(),
+        }
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        let item = std::mem::size_of::<T>();
+        match self {
+            Grid::Flat(data) => data.len() * item,
+            Grid::Chunked { chunks, .. } => {
+                chunks.len() * std::mem::size_of::<Chunk<T>>() + self.dense_chunks() * CHUNK_AREA * item
+            }
+        }
+    }
+}
+
+impl<T> Grid<T>
+where
+    T: Copy + PartialEq + Ord + Send + Sync,
+{
+    /// Largest value that isn't `skip`
+    pub fn max_except(&self, skip: T) -> Option<T> {
+        match self {
+            Grid::Flat(data) => data.par_iter().filter(|&&v| v != skip).max().copied(),
+            Grid::Chunked { chunks, .. } => chunks.par_iter()
+                .filter_map(|chunk| match chunk {
+                    Chunk::Uniform(v) => (*v != skip).then_some(*v),
+                    Chunk::Dense(data) => data.iter().filter(|&&v| v != skip).max().copied(),
+                })
+                .max(),
+        }
+    }
+}
+
+impl<T> Index<usize> for Grid<T>
+where
+    T: Copy + PartialEq + Send + Sync,
+{
+    type Output = T;
+
+    #[inline]
+    fn index(&self, index: usize) -> &T {
+        match self {
+            Grid::Flat(data) => &data[index],
+            Grid::Chunked { width, height, chunks_x, chunks } => {
+                assert!(index < width * height, "tile index {} out of bounds", index);
+                let (chunk, local) = Self::locate(*width, *chunks_x, index);
+                match &chunks[chunk] {
+                    Chunk::Uniform(v) => v,
+                    Chunk::Dense(data) => &data[local],
+                }
+            }
+        }
+    }
+}
+
+impl<T> IndexMut<usize> for Grid<T>
+where
+    T: Copy + PartialEq + Send + Sync,
+{
+    /// Allocates the chunk on first write, prefer `set` when the value may be unchanged
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        match self {
+            Grid::Flat(data) => &mut data[index],
+            Grid::Chunked { width, height, chunks_x, chunks } => {
+                assert!(index < *width * *height, "tile index {} out of bounds", index);
+                let (chunk, local) = Self::locate(*width, *chunks_x, index);
+                let slot = &mut chunks[chunk];
+                if let Chunk::Uniform(v) = *slot {
+                    *slot = Chunk::Dense(vec![v; CHUNK_AREA].into_boxed_slice());
+                }
+                match slot {
+                    Chunk::Dense(data) => &mut data[local],
+                    Chunk::Uniform(_) => unreachable!(),
+                }
+            }
+        }
+    }
+}
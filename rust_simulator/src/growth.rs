@@ -0,0 +1,489 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::{AutoGrowState, World};
+use crate::grid::Grid;
+use crate::queue::Queue;
+
+// Result of one group's expansion in isolation
+struct GroupRun {
+    // tiles claimed, with the cost they were claimed at
+    claims: Vec<AutoGrowState>,
+    // tiles popped while their empire could still grow, the only pops that act on anything
+    reached: Vec<usize>,
+    // tiles whose owner or search distance the expansion wrote
+    written: Vec<usize>,
+    // stopped early after running into another group, `reached` and `written` are partial
+    aborted: bool,
+}
+
+// Tiles per lazily allocated block of Marks, a 64x64 chunk's worth
+const MARK_BLOCK: usize = 1 << 12;
+
+#[derive(Default)]
+struct TileMarks {
+    reached: AtomicU32,
+    written: AtomicU32,
+}
+
+// First group (index + 1) to reach and to write each tile during a round. Lets a run stop
+// as soon as it meets another group instead of flooding the whole sea on its own.
+// Allocated in blocks of MARK_BLOCK tiles on first touch, so it costs what the searches reach.
+struct Marks {
+    len: usize,
+    blocks: Vec<OnceLock<Box<[TileMarks]>>>,
+}
+
+impl Marks {
+    fn new(len: usize) -> Marks {
+        Marks { len, blocks: (0..len.div_ceil(MARK_BLOCK)).map(|_| OnceLock::new()).collect() }
+    }
+
+    fn locate(&self, index: usize) -> (usize, usize) {
+        (index / MARK_BLOCK, index % MARK_BLOCK)
+    }
+
+    fn tile(&self, index: usize) -> &TileMarks {
+        let (block, local) = self.locate(index);
+        &self.blocks[block].get_or_init(|| (0..MARK_BLOCK).map(|_| TileMarks::default()).collect())[local]
+    }
+
+    // each returns true when another group already did the opposite access on the tile
+    fn reach(&self, index: usize, tag: u32) -> bool {
+        let tile = self.tile(index);
+        let _ = tile.reached.compare_exchange(0, tag, SeqCst, SeqCst);
+        let writer = tile.written.load(SeqCst);
+        writer != 0 && writer != tag
+    }
+
+    fn write(&self, index: usize, tag: u32) -> bool {
+        let tile = self.tile(index);
+        let _ = tile.written.compare_exchange(0, tag, SeqCst, SeqCst);
+        let reader = tile.reached.load(SeqCst);
+        reader != 0 && reader != tag
+    }
+
+    // tags of the first group to reach and to write the tile, when both happened
+    fn first(&self, index: usize) -> Option<(u32, u32)> {
+        let (block, local) = self.locate(index);
+        let tile = &self.blocks[block].get()?[local];
+        let (reader, writer) = (tile.reached.load(SeqCst), tile.written.load(SeqCst));
+        (reader != 0 && writer != 0).then_some((reader, writer))
+    }
+
+    // Clears the tiles a run marked, ready for the next round
+    fn clear(&self, run: &GroupRun) {
+        for &index in run.reached.iter().chain(&run.written) {
+            let (block, local) = self.locate(index);
+            if let Some(tiles) = self.blocks[block].get() {
+                tiles[local].reached.store(0, SeqCst);
+                tiles[local].written.store(0, SeqCst);
+            }
+        }
+    }
+}
+
+// One group's search distances and claims, all u32::MAX and 0 between runs
+struct GroupScratch {
+    dist: Grid<u32>,
+    claimed: Grid<u32>,
+}
+
+impl GroupScratch {
+    fn new(width: usize, height: usize, chunked: bool) -> GroupScratch {
+        GroupScratch { dist: Grid::new(width, height, u32::MAX, chunked), claimed: Grid::new(width, height, 0, chunked) }
+    }
+
+    // every tile a run changes is in its `written`
+    fn reset(&mut self, run: &GroupRun) {
+        for &index in &run.written {
+            self.dist.set(index, u32::MAX);
+            self.claimed.set(index, 0);
+        }
+    }
+}
+
+/// Search state of the parallel auto_grow, kept between calls: one scratch per group
+/// running at a time (at most one per thread) and the marks shared by a round.
+#[derive(Default)]
+pub(crate) struct GrowthScratch {
+    groups: Vec<GroupScratch>,
+    marks: Option<Marks>,
+}
+
+fn find(parent: &mut [usize], mut group: usize) -> usize {
+    while parent[group] != group {
+        parent[group] = parent[parent[group]];
+        group = parent[group];
+    }
+    group
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Runs `auto_grow` with groups of empires expanding on separate threads.
+    /// The result is the same as the sequential growth, only the order of the
+    /// changes inside the replay tick can differ. Empires that compete for the same
+    /// tiles end up in one group, so crowded maps gain little.
+    pub fn set_parallel_growth(&mut self, enabled: bool) { self.parallel_growth = enabled; }
+    pub fn parallel_growth(&self) -> bool { self.parallel_growth }
+}
+
+impl World {
+    /// Parallel `auto_grow`.
+    ///
+    /// Every empire first expands on its own against the tick's starting ownership.
+    /// In the shared search an empire can only be steered by another through a tile it
+    /// pops while it still has growth left, and only if the other empire wrote that tile
+    /// (claimed it or lowered its distance). Groups where that happens are merged and
+    /// expanded again as one search until no group reaches a tile another one wrote,
+    /// then every group's claims are exactly those of the sequential search.
+    /// The final groups are kept as the starting point of the next call.
+    pub(crate) fn auto_grow_parallel(&mut self, size: u32, use_resources: bool) {
+        let mut seeds: BTreeMap<u32, Vec<AutoGrowState>> = BTreeMap::new();
+        for seed in self.frontier_seeds(use_resources) {
+            seeds.entry(seed.empire_id).or_default().push(seed);
+        }
+
+        // last call's groups, empires without a frontier dropped and new ones on their own
+        let mut placed = HashSet::new();
+        let mut groups: Vec<Vec<u32>> = self.growth_groups.iter()
+            .map(|group| group.iter().copied().filter(|id| seeds.contains_key(id) && placed.insert(*id)).collect::<Vec<u32>>())
+            .filter(|group| !group.is_empty())
+            .collect();
+        groups.extend(seeds.keys().filter(|id| !placed.contains(*id)).map(|&id| vec![id]));
+        let mut runs: Vec<Option<GroupRun>> = groups.iter().map(|_| None).collect();
+
+        let (width, height, chunked) = (self.width, self.height, self.chunked);
+        let mut scratch = std::mem::take(&mut self.growth_scratch);
+        scratch.groups.retain(|group| group.dist.len() == width * height);
+        let pool = Mutex::new(scratch.groups);
+        let marks = match scratch.marks.take() {
+            Some(marks) if marks.len == width * height => marks,
+            _ => Marks::new(width * height),
+        };
+
+        loop {
+            let world = &*self;
+            // a lone group has nobody to run into
+            let round_marks = (groups.len() > 1).then_some(&marks);
+            let finished: Vec<(usize, GroupRun)> = runs.iter()
+                .enumerate()
+                .filter(|(_, run)| run.is_none())
+                .map(|(group, _)| group)
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .map(|group| {
+                    let group_seeds: Vec<AutoGrowState> = groups[group].iter()
+                        .flat_map(|id| seeds[id].iter().copied())
+                        .collect();
+                    let mut local = pool.lock().unwrap().pop().unwrap_or_else(|| GroupScratch::new(width, height, chunked));
+                    let run = world.expand_group(&group_seeds, size, use_resources, round_marks, group as u32 + 1, &mut local);
+                    local.reset(&run);
+                    pool.lock().unwrap().push(local);
+                    (group, run)
+                })
+                .collect();
+            for (group, run) in finished {
+                marks.clear(&run);
+                runs[group] = Some(run);
+            }
+            if groups.len() == 1 {
+                break;
+            }
+
+            // union each group with the other groups that wrote the tiles it reached. On a tile
+            // with both readers and writers that joins every group touching it, so it is enough
+            // to join each of them with the first reader and the first writer, kept in the marks.
+            let mut parent: Vec<usize> = (0..groups.len()).collect();
+            for (group, run) in runs.iter().enumerate() {
+                let run = run.as_ref().unwrap();
+                let tag = group as u32 + 1;
+                run.reached.iter().for_each(|&index| { marks.reach(index, tag); });
+                run.written.iter().for_each(|&index| { marks.write(index, tag); });
+            }
+
+            let mut merged = false;
+            for (group, run) in runs.iter().enumerate() {
+                let run = run.as_ref().unwrap();
+                for &index in run.reached.iter().chain(&run.written) {
+                    let Some((reader, writer)) = marks.first(index) else { continue };
+                    for other in [reader as usize - 1, writer as usize - 1] {
+                        let (a, b) = (find(&mut parent, group), find(&mut parent, other));
+                        if a != b {
+                            parent[a.max(b)] = a.min(b);
+                            merged = true;
+                        }
+                    }
+                }
+            }
+            for run in runs.iter().flatten() {
+                marks.clear(run);
+            }
+            if !merged {
+                // a run only stops when it met another group, which the lists above catch
+                debug_assert!(runs.iter().all(|run| !run.as_ref().unwrap().aborted));
+                break;
+            }
+
+            let mut next_groups: Vec<Vec<u32>> = Vec::new();
+            let mut next_runs: Vec<Option<GroupRun>> = Vec::new();
+            let mut slot_of_root: HashMap<usize, usize> = HashMap::new();
+            for (group, run) in runs.into_iter().enumerate() {
+                let root = find(&mut parent, group);
+                match slot_of_root.get(&root) {
+                    Some(&slot) => {
+                        next_groups[slot].extend(&groups[group]);
+                        next_runs[slot] = None;
+                    }
+                    None => {
+                        slot_of_root.insert(root, next_groups.len());
+                        next_groups.push(groups[group].clone());
+                        next_runs.push(run);
+                    }
+                }
+            }
+            groups = next_groups;
+            runs = next_runs;
+        }
+
+        self.growth_groups = groups;
+        let mut groups = pool.into_inner().unwrap();
+        for group in &mut groups {
+            group.dist.compact();
+            group.claimed.compact();
+        }
+        self.growth_scratch = GrowthScratch { groups, marks: Some(marks) };
+
+        // groups are disjoint, apply them in state order so the replay is deterministic
+        let mut claims: Vec<AutoGrowState> = runs.into_iter()
+            .flat_map(|run| run.unwrap().claims)
+            .collect();
+        claims.sort_unstable_by(|a, b| b.cmp(a));
+
+        for claim in claims {
+            self.set_owner(claim.index, claim.empire_id);
+            self.dist_vector[claim.index] = claim.true_cost;
+        }
+
+        self.commit_tick();
+    }
+
+    // The auto_grow expansion loop for a subset of the empires, with the ownership
+    // and search state it changes kept in `scratch` so the world stays untouched
+    fn expand_group(&self, seeds: &[AutoGrowState], size: u32, use_resources: bool, marks: Option<&Marks>, tag: u32, scratch: &mut GroupScratch) -> GroupRun {
+        let width = self.width;
+        let height = self.height;
+        let directions = [(0, -1), (0, 1), (-1, 0), (1, 0)];
+
+        let mut pq = Queue::<AutoGrowState>::new(self.queue_backend);
+        let mut grow_counts: HashMap<u32, u32> = HashMap::new();
+        let GroupScratch { dist: local_dist, claimed } = scratch;
+        let mut claims = Vec::new();
+        let mut reached = Vec::new();
+        let mut written = Vec::new();
+
+        let owner = |claimed: &Grid<u32>, index: usize| {
+            match claimed[index] {
+                0 => self.owners[index],
+                id => id,
+            }
+        };
+
+        for &seed in seeds {
+            pq.push(seed);
+        }
+
+        while let Some(state) = pq.pop() {
+            let AutoGrowState { sort_cost: _, true_cost, index, empire_id } = state;
+
+            let current_growth = grow_counts.entry(empire_id).or_insert(0);
+            if *current_growth < size {
+                reached.push(index);
+                if marks.is_some_and(|marks| marks.reach(index, tag)) {
+                    return GroupRun { claims, reached, written, aborted: true };
+                }
+            }
+
+            if owner(claimed, index) != 0 { continue; }
+
+            if true_cost > local_dist[index] { continue; }
+
+            let current_growth = grow_counts.entry(empire_id).or_insert(0);
+            if *current_growth >= size { continue; }
+
+            if self.tiles[index].is_liveable() {
+                claimed[index] = empire_id;
+                local_dist[index] = true_cost;
+                written.push(index);
+                if marks.is_some_and(|marks| marks.write(index, tag)) {
+                    return GroupRun { claims, reached, written, aborted: true };
+                }
+                claims.push(state);
+
+                *current_growth += 1;
+            }
+
+            let empire = self.empires.get(&empire_id).unwrap();
+            let (costs, resource_values) = (empire.costs, empire.resource_values);
+            let x = (index % width) as i32;
+            let y = (index / width) as i32;
+            let current_terrain = self.tiles[index];
+
+            for (dx, dy) in directions {
+                let nx = x + dx;
+                let ny = y + dy;
+                if nx < 0 || nx >= width as i32 || ny < 0 || ny >= height as i32 { continue; }
+
+                let neib_idx = (ny as usize * width) + (nx as usize);
+                if owner(claimed, neib_idx) != 0 { continue; }
+
+                let neib_terrain = self.tiles[neib_idx];
+                let move_cost = costs[neib_terrain as usize];
+                let is_transition = current_terrain.is_watery() != neib_terrain.is_watery();
+                let penalty = if is_transition { costs[1] * 3 } else { 0 };
+
+                let new_true_cost = true_cost.saturating_add(move_cost).saturating_add(penalty);
+
+                let new_sort_cost = if use_resources {
+                    let resource_val = resource_values[self.resources[neib_idx] as usize];
+                    new_true_cost / (1 + resource_val)
+                } else {
+                    new_true_cost
+                };
+
+                if new_true_cost < local_dist[neib_idx] {
+                    local_dist[neib_idx] = new_true_cost;
+                    written.push(neib_idx);
+                    if marks.is_some_and(|marks| marks.write(neib_idx, tag)) {
+                        return GroupRun { claims, reached, written, aborted: true };
+                    }
+                    pq.push(AutoGrowState {
+                        sort_cost: new_sort_cost,
+                        true_cost: new_true_cost,
+                        index: neib_idx,
+                        empire_id
+                    });
+                }
+            }
+        }
+
+        reached.sort_unstable();
+        reached.dedup();
+        written.sort_unstable();
+        written.dedup();
+
+        GroupRun { claims, reached, written, aborted: false }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::brush::mix;
+    use crate::utlis::INTI_COSTS;
+    use crate::{Resource, World};
+
+    const WIDTH: usize = 120;
+    const HEIGHT: usize = 80;
+    const CAPITALS: usize = 4;
+
+    // Plains around a lake, cut by a river and a mountain range with passes, with scattered
+    // forest, a desert corner and resources, capitals on a 4x4 grid
+    fn handmade_map() -> (String, String, Vec<(usize, usize)>) {
+        let capitals: Vec<(usize, usize)> = (0..CAPITALS * CAPITALS)
+            .map(|i| (12 + (i % CAPITALS) * 30, 8 + (i / CAPITALS) * 20))
+            .collect();
+        let (mut map, mut resources) = (String::new(), String::new());
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let (dx, dy) = (x as i64 - 60, y as i64 - 40);
+                let terrain = match () {
+                    _ if capitals.contains(&(x, y)) => 'P',
+                    _ if dx * dx + 2 * dy * dy < 120 => 'W',
+                    _ if x == 40 => 'R',
+                    _ if (50..53).contains(&y) && x % 17 != 0 => 'M',
+                    _ if x > 95 && y < 25 => 'D',
+                    _ if (x * y) % 13 == 0 => 'F',
+                    _ => 'P',
+                };
+                map.push(terrain);
+                resources.push(b"..g..w.C..f.!.sv.*c.S."[(x * 31 + y * 17) % 22] as char);
+            }
+            map.push('\n');
+            resources.push('\n');
+        }
+        (map, resources, capitals)
+    }
+
+    fn grow_handmade(chunked: bool, parallel: bool, use_resources: bool) -> (Vec<u32>, Vec<u32>) {
+        let (map, resources, capitals) = handmade_map();
+        let mut world = if chunked { World::new_chunked(&map, Some(resources)) } else { World::new(&map, Some(resources)) };
+        for (i, &(x, y)) in capitals.iter().enumerate() {
+            assert!(world.add_empire(x, y, i as u32 + 1, 0xFF000000 | i as u32, 10, INTI_COSTS.to_vec()));
+        }
+        world.set_parallel_growth(parallel);
+
+        // several calls, so the groups and scratch buffers of one call are reused by the next
+        for tick in 0..12 {
+            world.auto_grow(40 + 30 * tick, use_resources);
+        }
+        (world.owners.to_vec(), world.dist_vector.to_vec())
+    }
+
+    #[test]
+    fn parallel_growth_matches_sequential_on_a_handmade_map() {
+        for (chunked, use_resources) in [(false, false), (false, true), (true, false), (true, true)] {
+            let (owners, dist) = grow_handmade(chunked, false, use_resources);
+            let contacts = (0..owners.len())
+                .filter(|&i| i % WIDTH + 1 < WIDTH && owners[i] != 0 && owners[i + 1] != 0 && owners[i] != owners[i + 1])
+                .count();
+            assert!(contacts > 50, "empires barely meet ({} contacts), the groups never merge", contacts);
+
+            let parallel = grow_handmade(chunked, true, use_resources);
+            assert!(owners == parallel.0, "chunked {}, resources {}: owners differ", chunked, use_resources);
+            assert!(dist == parallel.1, "chunked {}, resources {}: growth costs differ", chunked, use_resources);
+        }
+    }
+
+    const MAPS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../Map_Simulator_Optimized/public/resources/maps");
+    // small maps where the empires share hundreds of border tiles after TICKS
+    const MAPS: [&str; 3] = ["africa", "europe", "south_america"];
+    const EMPIRES: u32 = 12;
+    const TICKS: usize = 8;
+
+    // Same map, capitals and resources for both runs, grown until the empires meet
+    fn grow(map: &str, chunked: bool, parallel: bool, use_resources: bool) -> (Vec<u32>, Vec<u32>) {
+        let mut world = if chunked { World::new_chunked(map, None) } else { World::new(map, None) };
+        for index in 0..world.width * world.height {
+            if world.tiles[index].is_liveable() && mix(index as u64).is_multiple_of(7) {
+                world.resources.set(index, Resource::from_u8((mix(index as u64 ^ 0xFF) % 11) as u8));
+            }
+        }
+        world.auto_place_empires(EMPIRES, 10, 7, 20, None).expect("capitals fit on the bundled maps");
+        world.set_parallel_growth(parallel);
+
+        for tick in 0..TICKS {
+            world.auto_grow(200 + 100 * tick as u32, use_resources);
+        }
+        (world.owners.to_vec(), world.dist_vector.to_vec())
+    }
+
+    #[test]
+    fn parallel_growth_matches_sequential() {
+        for name in MAPS {
+            let map = std::fs::read_to_string(format!("{}/{}.txt", MAPS_DIR, name)).expect("bundled map not found");
+            for (chunked, use_resources) in [(false, false), (false, true), (true, true)] {
+                let sequential = grow(&map, chunked, false, use_resources);
+                let parallel = grow(&map, chunked, true, use_resources);
+
+                let differing = (0..sequential.0.len()).filter(|&i| sequential.0[i] != parallel.0[i]).count();
+                assert_eq!(differing, 0, "{} (chunked {}, resources {}): {} owners differ", name, chunked, use_resources, differing);
+                assert!(sequential.1 == parallel.1, "{} (chunked {}, resources {}): growth costs differ", name, chunked, use_resources);
+            }
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::composite::{RenderLayer, ownership_opacity};
+use crate::dirty::{Bounds, Layer};
+use crate::utlis::map_size;
+use crate::{Resource, Terrain, World};
+
+pub(crate) const TERRAINS: [Terrain; 8] = [
+    Terrain::Unknown, Terrain::Water, Terrain::River, Terrain::Plain,
+    Terrain::Mountain, Terrain::Desert, Terrain::Forest, Terrain::Ice,
+];
+
+pub(crate) const RESOURCES: [Resource; 11] = [
+    Resource::None, Resource::Gold, Resource::Silver, Resource::Gems, Resource::Coal, Resource::Cows,
+    Resource::Wheat, Resource::Fish, Resource::Silk, Resource::Spices, Resource::Wine,
+];
+
+/// Decoded image, pixels in the render buffer layout (0xAABBGGRR)
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+#[inline]
+fn rgba(r: u8, g: u8, b: u8, a: u8) -> u32 {
+    ((a as u32) << 24) | ((b as u32) << 16) | ((g as u32) << 8) | r as u32
+}
+
+/// PNG, BMP (8, 24 and 32 bit, uncompressed) or PGM (P2/P5), picked by the magic bytes
+pub fn decode(bytes: &[u8]) -> Result<Raster, String> {
+    if bytes.starts_with(b"\x89PNG") {
+        decode_png(bytes)
+    } else if bytes.starts_with(b"BM") {
+        decode_bmp(bytes)
+    } else if bytes.starts_with(b"P5") || bytes.starts_with(b"P2") {
+        decode_pgm(bytes)
+    } else {
+        Err("unsupported image, expected PNG, BMP or PGM".to_string())
+    }
+}
+
+fn decode_png(bytes: &[u8]) -> Result<Raster, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    // palettes and low bit depths expanded, 16 bit channels cut to 8
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| format!("PNG: {}", e))?;
+
+    let mut data = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut data).map_err(|e| format!("PNG: {}", e))?;
+    let channels = frame.color_type.samples();
+    let (width, height) = (frame.width as usize, frame.height as usize);
+
+    let pixels = data[..frame.buffer_size()]
+        .par_chunks(frame.line_size)
+        .flat_map_iter(|line| {
+            line[..width * channels].chunks_exact(channels).map(move |p| match p {
+                [v] => rgba(*v, *v, *v, 0xFF),
+                [v, a] => rgba(*v, *v, *v, *a),
+                [r, g, b] => rgba(*r, *g, *b, 0xFF),
+                [r, g, b, a] => rgba(*r, *g, *b, *a),
+                _ => unreachable!(),
+            })
+        })
+        .collect();
+
+    Ok(Raster { width, height, pixels })
+}
+
+fn decode_bmp(bytes: &[u8]) -> Result<Raster, String> {
+    let u16_at = |at: usize| bytes.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let truncated = || "BMP: file is truncated".to_string();
+
+    let data_offset = u32_at(10).ok_or_else(truncated)? as usize;
+    let header_size = u32_at(14).ok_or_else(truncated)? as usize;
+    let width = u32_at(18).ok_or_else(truncated)? as i32;
+    let height = u32_at(22).ok_or_else(truncated)? as i32;
+    let bits = u16_at(28).ok_or_else(truncated)?;
+    let compression = u32_at(30).ok_or_else(truncated)?;
+
+    if width <= 0 || height == 0 {
+        return Err(format!("BMP: invalid size {}x{}", width, height));
+    }
+    if ![8, 24, 32].contains(&bits) {
+        return Err(format!("BMP: {} bits per pixel is not supported", bits));
+    }
+    // BI_RGB, or BI_BITFIELDS with the usual BGRA masks
+    if compression != 0 && !(compression == 3 && bits == 32) {
+        return Err(format!("BMP: compression {} is not supported", compression));
+    }
+
+    let (width, top_down) = (width as usize, height < 0);
+    let height = height.unsigned_abs() as usize;
+    // sizes come from the file, anything that overflows can't fit in it either
+    let stride = (bits as usize).checked_mul(width).ok_or_else(truncated)?.div_ceil(32) * 4;
+    let end = stride.checked_mul(height).and_then(|len| len.checked_add(data_offset)).ok_or_else(truncated)?;
+    if bytes.len() < end {
+        return Err(truncated());
+    }
+
+    let palette: Vec<u32> = if bits == 8 {
+        // entries past 256 can't be indexed by a byte
+        let count = match u32_at(46).ok_or_else(truncated)? { 0 => 256, n => (n as usize).min(256) };
+        let start = 14usize.checked_add(header_size).ok_or_else(truncated)?;
+        (0..count)
+            .map(|i| start.checked_add(i * 4)
+                .and_then(|at| bytes.get(at..at.checked_add(3)?))
+                .map(|c| rgba(c[2], c[1], c[0], 0xFF))
+                .ok_or_else(truncated))
+            .collect::<Result<_, _>>()?
+    } else {
+        Vec::new()
+    };
+
+    // at least one byte per pixel was checked above, so this is bounded by the file size
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let line = &bytes[data_offset + row * stride..data_offset + (row + 1) * stride];
+        for x in 0..width {
+            let pixel = match bits {
+                8 => *palette.get(line[x] as usize).ok_or("BMP: colour index outside the palette")?,
+                24 => rgba(line[x * 3 + 2], line[x * 3 + 1], line[x * 3], 0xFF),
+                // the alpha byte of BI_RGB files is usually left at 0
+                32 => rgba(line[x * 4 + 2], line[x * 4 + 1], line[x * 4], 0xFF),
+                other => return Err(format!("BMP: {} bits per pixel is not supported", other)),
+            };
+            pixels.push(pixel);
+        }
+    }
+
+    Ok(Raster { width, height, pixels })
+}
+
+fn decode_pgm(bytes: &[u8]) -> Result<Raster, String> {
+    // header: magic, width, height, maxval, separated by whitespace and # comments
+    let mut pos = 2;
+    let mut field = || -> Result<usize, String> {
+        loop {
+            match bytes.get(pos) {
+                Some(b'#') => while bytes.get(pos).is_some_and(|&c| c != b'\n') { pos += 1 },
+                Some(c) if c.is_ascii_whitespace() => pos += 1,
+                _ => break,
+            }
+        }
+        let start = pos;
+        while bytes.get(pos).is_some_and(|c| c.is_ascii_digit()) {
+            pos += 1;
+        }
+        std::str::from_utf8(&bytes[start..pos]).ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| "PGM: malformed header".to_string())
+    };
+
+    let width = field()?;
+    let height = field()?;
+    let max = field()?;
+    if max == 0 || max > 65535 {
+        return Err(format!("PGM: invalid maximum value {}", max));
+    }
+
+    let size = width.checked_mul(height).ok_or("PGM: file is truncated")?;
+    let scale = |v: usize| (v.min(max) * 255 / max) as u8;
+    let values: Vec<u8> = if bytes.starts_with(b"P5") {
+        // exactly one whitespace byte before the raster
+        let start = pos + 1;
+        let sample = if max > 255 { 2 } else { 1 };
+        let raster = size.checked_mul(sample)
+            .and_then(|len| bytes.get(start..start.checked_add(len)?))
+            .ok_or("PGM: file is truncated")?;
+        raster.chunks_exact(sample)
+            .map(|s| scale(s.iter().fold(0, |v, &b| (v << 8) | b as usize)))
+            .collect()
+    } else {
+        (0..size).map(|_| field().map(scale)).collect::<Result<_, _>>()?
+    };
+
+    let pixels = values.into_iter().map(|v| rgba(v, v, v, 0xFF)).collect();
+    Ok(Raster { width, height, pixels })
+}
+
+// Largest raw RGBA image encode_png builds in memory
+const MAX_PNG_BYTES: usize = 1 << 29;
+
+/// RGBA PNG of a render buffer, every tile drawn as a `scale` x `scale` block
+pub fn encode_png(pixels: &[u32], width: usize, height: usize, scale: usize) -> Result<Vec<u8>, String> {
+    let bytes = pixels.len().checked_mul(scale)
+        .and_then(|n| n.checked_mul(scale))
+        .and_then(|n| n.checked_mul(4))
+        .filter(|&bytes| bytes <= MAX_PNG_BYTES)
+        .ok_or_else(|| format!("{}x{} at scale {} is too large for a PNG", width, height, scale))?;
+    let mut data = Vec::with_capacity(bytes);
+    for row in pixels.chunks(width.max(1)) {
+        let line: Vec<u8> = row.iter()
+            .flat_map(|&p| std::iter::repeat_n(p.to_le_bytes(), scale))
+            .flatten()
+            .collect();
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+
+    let mut out = Vec::new();
+    // both fit, their product with the 4 bytes a pixel was checked above
+    let mut encoder = png::Encoder::new(&mut out, (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| format!("PNG: {}", e))?;
+    writer.write_image_data(&data).map_err(|e| format!("PNG: {}", e))?;
+    writer.finish().map_err(|e| format!("PNG: {}", e))?;
+
+    Ok(out)
+}
+
+// Squared RGBA distance, so transparent pixels land on transparent palette entries
+#[inline]
+fn distance(a: u32, b: u32) -> u32 {
+    (0..4).map(|shift| {
+        let d = ((a >> (shift * 8)) & 0xFF) as i32 - ((b >> (shift * 8)) & 0xFF) as i32;
+        (d * d) as u32
+    }).sum()
+}
+
+fn nearest<T: Copy>(color: u32, palette: &[(u32, T)]) -> T {
+    palette.iter().min_by_key(|(c, _)| distance(color, *c)).unwrap().1
+}
+
+// Caller's colours indexed by the enum value, or the render colours
+fn palette<T: Copy>(values: &[T], custom: Option<Vec<u32>>, default: impl Fn(T) -> u32, what: &str) -> Result<Vec<(u32, T)>, String> {
+    match custom {
+        None => Ok(values.iter().map(|&v| (default(v), v)).collect()),
+        Some(colors) if colors.len() == values.len() => Ok(colors.into_iter().zip(values.iter().copied()).collect()),
+        Some(colors) => Err(format!("{} palette needs {} colours, got {}", what, values.len(), colors.len())),
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Builds a world from a PNG, BMP or PGM image, one tile per pixel, each pixel taking the
+    /// terrain with the nearest colour. `palette` holds 8 colours (0xAABBGGRR) indexed by the
+    /// Terrain value (Unknown, Water, River, ...), by default the terrain render colours.
+    pub fn from_image(bytes: &[u8], palette: Option<Vec<u32>>) -> Result<World, String> {
+        let palette = self::palette(&TERRAINS, palette, |t| t.get_color(), "terrain")?;
+        let raster = decode(bytes)?;
+
+        let tiles = raster.pixels.par_iter().map(|&color| nearest(color, &palette)).collect();
+        let resources = vec![Resource::None; raster.width * raster.height];
+
+        Ok(World::from_grids(raster.width, raster.height, tiles, resources, false))
+    }
+
+    /// Replaces the resources with an image overlay of the map's size, matched the same way
+    /// as `from_image`. `palette` holds 11 colours indexed by the Resource value, None first
+    /// (transparent by default, so transparent pixels carry no resource).
+    pub fn import_resource_image(&mut self, bytes: &[u8], palette: Option<Vec<u32>>) -> Result<(), String> {
+        let palette = self::palette(&RESOURCES, palette, |r| r.get_color(), "resource")?;
+        let raster = decode(bytes)?;
+        if raster.width != self.width || raster.height != self.height {
+            return Err(format!("image is {}x{} but the map is {}x{}", raster.width, raster.height, self.width, self.height));
+        }
+
+        let resources: Vec<Resource> = raster.pixels.par_iter().map(|&color| nearest(color, &palette)).collect();
+        for (index, resource) in resources.into_iter().enumerate() {
+            self.resources.set(index, resource);
+        }
+        self.dirty.mark_layer(Layer::Resources);
+
+        Ok(())
+    }
+
+    /// Encodes the layers selected in `layer_mask` (see RenderLayer) as a PNG, blended the same
+    /// way as render_composite, each tile `scale` pixels wide. The distance layer is rendered
+    /// first if it never was. Blended into its own buffer, so the composite and the dirty
+    /// rects of the canvases are left as they were.
+    pub fn export_png(&mut self, layer_mask: u32, scale: u32, ownership_alpha: f32) -> Result<Vec<u8>, String> {
+        if scale == 0 {
+            return Err("scale must be at least 1".to_string());
+        }
+        let size = map_size(self.width as u64, self.height as u64).ok_or("the map is empty")?;
+        let scaled = |side: usize| side.checked_mul(scale as usize).filter(|&side| side <= u32::MAX as usize);
+        if scaled(self.width).is_none() || scaled(self.height).is_none() {
+            return Err(format!("{}x{} at scale {} is too large for a PNG", self.width, self.height, scale));
+        }
+
+        if RenderLayer::Distance as u32 & layer_mask != 0 && !self.dist_rendered {
+            self.render_dist_map(None);
+        }
+        self.refresh_layers(layer_mask);
+
+        let mut pixels = vec![0; size];
+        let full = Bounds { min_x: 0, min_y: 0, max_x: self.width - 1, max_y: self.height - 1 };
+        self.blend_layers(layer_mask, ownership_opacity(ownership_alpha), full, &mut pixels);
+
+        encode_png(&pixels, self.width, self.height, scale as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Uncompressed BMP with a 40 byte info header, `rows` already padded to 4 bytes
+    fn bmp(width: i32, height: i32, bits: u16, palette: &[[u8; 4]], rows: &[u8]) -> Vec<u8> {
+        let data_offset = 54 + palette.len() as u32 * 4;
+        let mut bytes = b"BM".to_vec();
+        bytes.extend((data_offset + rows.len() as u32).to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(data_offset.to_le_bytes());
+        bytes.extend(40u32.to_le_bytes());
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(bits.to_le_bytes());
+        bytes.extend([0; 16]);
+        bytes.extend((palette.len() as u32).to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(palette.iter().flatten());
+        bytes.extend(rows);
+        bytes
+    }
+
+    #[test]
+    fn png_round_trips_through_encode() {
+        let pixels = [rgba(255, 0, 0, 255), rgba(0, 255, 0, 128), rgba(0, 0, 255, 0), rgba(1, 2, 3, 4), rgba(9, 9, 9, 255), 0];
+        let raster = decode(&encode_png(&pixels, 3, 2, 1).unwrap()).unwrap();
+        assert_eq!((raster.width, raster.height), (3, 2));
+        assert_eq!(raster.pixels, pixels);
+
+        // every tile becomes a 2x2 block
+        let scaled = decode(&encode_png(&pixels, 3, 2, 2).unwrap()).unwrap();
+        assert_eq!((scaled.width, scaled.height), (6, 4));
+        assert_eq!(scaled.pixels[..6], [pixels[0], pixels[0], pixels[1], pixels[1], pixels[2], pixels[2]]);
+        assert_eq!(scaled.pixels[18], pixels[3]);
+
+        assert!(encode_png(&pixels, 3, 2, 1 << 20).is_err());
+    }
+
+    #[test]
+    fn truncated_pngs_are_errors() {
+        let png = encode_png(&[rgba(1, 2, 3, 255); 16], 4, 4, 1).unwrap();
+        for len in [4, 20, png.len() / 2, png.len() - 13] {
+            assert!(decode(&png[..len]).is_err(), "{} of {} bytes decoded", len, png.len());
+        }
+    }
+
+    #[test]
+    fn bmp_rows_are_read_bottom_up_unless_the_height_is_negative() {
+        // 2x2 at 24 bits, 6 bytes a row padded to 8, pixels stored as BGR
+        let rows = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 9, 9, 9, 0, 0];
+        let (red, green, blue, grey) = (rgba(255, 0, 0, 255), rgba(0, 255, 0, 255), rgba(0, 0, 255, 255), rgba(9, 9, 9, 255));
+
+        let bottom_up = decode(&bmp(2, 2, 24, &[], &rows)).unwrap();
+        assert_eq!((bottom_up.width, bottom_up.height), (2, 2));
+        assert_eq!(bottom_up.pixels, [blue, grey, red, green]);
+        assert_eq!(decode(&bmp(2, -2, 24, &[], &rows)).unwrap().pixels, [red, green, blue, grey]);
+
+        // 32 bits ignores the alpha byte
+        let raster = decode(&bmp(1, 1, 32, &[], &[3, 2, 1, 0])).unwrap();
+        assert_eq!(raster.pixels, [rgba(1, 2, 3, 255)]);
+    }
+
+    #[test]
+    fn bmp_palette_indices_are_checked() {
+        let palette = [[0, 0, 255, 0], [255, 255, 255, 0]];
+        let raster = decode(&bmp(3, 1, 8, &palette, &[1, 0, 1, 0])).unwrap();
+        assert_eq!(raster.pixels, [rgba(255, 255, 255, 255), rgba(255, 0, 0, 255), rgba(255, 255, 255, 255)]);
+
+        assert!(decode(&bmp(3, 1, 8, &palette, &[1, 2, 1, 0])).is_err());
+    }
+
+    #[test]
+    fn broken_bmps_are_errors() {
+        let image = bmp(2, 2, 24, &[], &[0; 16]);
+        for len in [2, 20, 30, image.len() - 1] {
+            assert!(decode(&image[..len]).is_err(), "{} of {} bytes decoded", len, image.len());
+        }
+
+        assert!(decode(&bmp(0, 2, 24, &[], &[0; 16])).is_err());
+        assert!(decode(&bmp(2, 2, 16, &[], &[0; 16])).is_err());
+        // sizes whose raster can't be in the file, or can't even be computed
+        assert!(decode(&bmp(i32::MAX, i32::MIN, 32, &[], &[0; 16])).is_err());
+        assert!(decode(&bmp(65536, 65536, 24, &[], &[0; 16])).is_err());
+
+        let mut compressed = image.clone();
+        compressed[30] = 1;
+        assert!(decode(&compressed).is_err());
+    }
+
+    #[test]
+    fn pgm_scales_samples_to_8_bits() {
+        let ascii = decode(b"P2\n# a comment\n3 1\n# another\n10\n0 5 10\n").unwrap();
+        assert_eq!((ascii.width, ascii.height), (3, 1));
+        assert_eq!(ascii.pixels, [rgba(0, 0, 0, 255), rgba(127, 127, 127, 255), rgba(255, 255, 255, 255)]);
+
+        let binary = decode(b"P5 2 1 255\n\x10\xF0").unwrap();
+        assert_eq!(binary.pixels, [rgba(16, 16, 16, 255), rgba(240, 240, 240, 255)]);
+
+        // 16 bit samples are big endian
+        let wide = decode(b"P5 2 1 65535\n\xFF\xFF\x80\x00").unwrap();
+        assert_eq!(wide.pixels, [rgba(255, 255, 255, 255), rgba(127, 127, 127, 255)]);
+    }
+
+    #[test]
+    fn broken_pgms_are_errors() {
+        assert!(decode(b"P5 2 2 255\n\x10\xF0\x00").is_err());
+        assert!(decode(b"P2 2 1 10\n3").is_err());
+        assert!(decode(b"P2 2 1 0\n0 0").is_err());
+        assert!(decode(b"P2 x 1 10\n0").is_err());
+        assert!(decode(b"P5 99999999999 99999999999 255\n").is_err());
+        assert!(decode(b"GIF89a").is_err());
+    }
+}
@@ -1,14 +1,13 @@
-use std::{cmp::Ordering, collections::{BinaryHeap, HashMap}, u32};
+use std::{cmp::Ordering, collections::HashMap};
+
+use rayon::prelude::*;
 
 use wasm_bindgen::prelude::*;
-use rayon::{prelude::*}; 
 
 pub use wasm_bindgen_rayon::init_thread_pool;
 
-mod utlis;
-use utlis::INTI_COSTS;
-
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
@@ -16,11 +15,59 @@ extern "C" {
 }
 
 // a macro that mimics println!
+// native builds have no console, so the message is dropped there
 macro_rules! console_log {
     // This pattern matches arguments exactly like println! does
-    ($($t:tt)*) => (log(&format!($($t)*)))
+    ($($t:tt)*) => {
+        #[cfg(target_arch = "wasm32")]
+        log(&format!($($t)*));
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = format_args!($($t)*);
+    }
 }
 
+// declared after console_log! so the modules can use it
+mod utlis;
+mod replay;
+mod dirty;
+mod composite;
+mod viewport;
+mod grid;
+mod queue;
+mod growth;
+mod frontier;
+mod mapfile;
+mod image;
+mod geojson;
+mod rle;
+mod fill;
+mod draw;
+mod brush;
+mod clipboard;
+mod canvas;
+mod filters;
+mod validate;
+mod components;
+mod placement;
+mod archetype;
+
+use replay::Replay;
+use dirty::{Bounds, DirtyRegions, Layer, par_render_rect, rect};
+use grid::Grid;
+use queue::Queue;
+use frontier::Frontier;
+use brush::Brush;
+use growth::GrowthScratch;
+pub use queue::QueueBackend;
+pub use composite::RenderLayer;
+pub use mapfile::WrapMode;
+pub use brush::BrushShape;
+pub use clipboard::{Clipboard, PasteMode};
+pub use canvas::Anchor;
+pub use validate::{Issue, IssueKind};
+pub use components::Components;
+pub use archetype::{Archetype, ArchetypeInfo};
+
  
     // Maps t (0.0 to 1.0) to a u32 Color (0xAABBGGRR Little Endian)
     // 0.0 = Red (Hot/Close)
@@ -68,6 +115,29 @@ macro_rules! console_log {
         (a << 24) | (b << 16) | (g << 8) | r
     }
 
+    // Scales the R, G and B channels of a 0xAABBGGRR color, alpha is kept
+    fn darken_color(color: u32, factor: f32) -> u32 {
+        let scale = |shift: u32| ((((color >> shift) & 0xFF) as f32 * factor) as u32).min(0xFF) << shift;
+        (color & 0xFF000000) | scale(16) | scale(8) | scale(0)
+    }
+
+    // Chunked worlds start with empty render buffers, they are allocated on their first render
+    fn ensure_buffer(buffer: &mut Vec<u32>, size: usize) {
+        if buffer.len() != size {
+            *buffer = vec![0x00000000; size];
+        }
+    }
+
+    // Color an owner id is drawn with, recorded replay colors win over the live empires
+    fn owner_color(empires: &HashMap<u32, Empire>, palette: Option<&HashMap<u32, u32>>, owner_id: u32) -> u32 {
+        let recorded = palette.and_then(|p| p.get(&owner_id).copied());
+        match (recorded, empires.get(&owner_id)) {
+            (Some(color), _) => color,
+            (None, Some(emp)) => emp.color,
+            (None, None) => 0xFFFFFFFF 
+        }
+    }
+
 
 
 
@@ -105,11 +175,13 @@ struct AutoGrowState {
 }
 
 // implement ordering to prioritize lowest sort_cost, then lowest true_cost
+// empire_id settles ties on the same tile so every queue backend pops in the same order
 impl Ord for AutoGrowState {
     fn cmp(&self, other: &Self) -> Ordering {
         other.sort_cost.cmp(&self.sort_cost) 
             .then_with(|| other.true_cost.cmp(&self.true_cost)) 
             .then_with(|| self.index.cmp(&other.index))
+            .then_with(|| self.empire_id.cmp(&other.empire_id))
     }
 }
 
@@ -153,19 +225,25 @@ impl Terrain {
     }
 
     fn from_char(c: char) -> Terrain {
+        Terrain::try_from_char(c).unwrap_or(Terrain::Unknown)
+    }
+
+    // strict version for the map file parser, '?' is the exported Unknown
+    fn try_from_char(c: char) -> Option<Terrain> {
         match c {
-            'W' => Terrain::Water,
-            'R' => Terrain::River,
-            'P' => Terrain::Plain,
-            'M' => Terrain::Mountain,
-            'D' => Terrain::Desert,
-            'F' => Terrain::Forest,
-            'I' => Terrain::Ice,
-            _ => Terrain::Unknown,
+            'W' => Some(Terrain::Water),
+            'R' => Some(Terrain::River),
+            'P' => Some(Terrain::Plain),
+            'M' => Some(Terrain::Mountain),
+            'D' => Some(Terrain::Desert),
+            'F' => Some(Terrain::Forest),
+            'I' => Some(Terrain::Ice),
+            '?' => Some(Terrain::Unknown),
+            _ => None,
         }
     }
 
-    fn to_char(&self) -> char {
+    fn to_char(self) -> char {
         match self {
             Terrain::Water => 'W',
             Terrain::River => 'R',
@@ -192,17 +270,11 @@ impl Terrain {
     }
 
     fn is_liveable(&self) -> bool{
-        match self {
-            Terrain::Water | Terrain::Unknown => false,
-            _ => true,
-        }
+        !matches!(self, Terrain::Water | Terrain::Unknown)
     }
 
     fn is_watery(&self) -> bool{
-        match self{
-            Terrain::Water | Terrain::River => true,
-            _ => false,
-        }
+        matches!(self, Terrain::Water | Terrain::River)
     }
 }
 
@@ -246,18 +318,24 @@ impl Resource {
     // Characters for map string export/import
     // g=Gold, s=Silver, *=Gems, c=Coal, C=Cows, w=Wheat, f=Fish, S=Silk, !=Spices, v=Wine
     pub fn from_char(c: char) -> Resource {
+        Resource::try_from_char(c).unwrap_or(Resource::None)
+    }
+
+    // strict version for the map file parser, '.' is the exported None
+    pub fn try_from_char(c: char) -> Option<Resource> {
         match c {
-            'g' => Resource::Gold,
-            's' => Resource::Silver,
-            '*' => Resource::Gems,
-            'c' => Resource::Coal,
-            'C' => Resource::Cows,
-            'w' => Resource::Wheat,
-            'f' => Resource::Fish,
-            'S' => Resource::Silk,
-            '!' => Resource::Spices,
-            'v' => Resource::Wine,
-            _ => Resource::None,
+            'g' => Some(Resource::Gold),
+            's' => Some(Resource::Silver),
+            '*' => Some(Resource::Gems),
+            'c' => Some(Resource::Coal),
+            'C' => Some(Resource::Cows),
+            'w' => Some(Resource::Wheat),
+            'f' => Some(Resource::Fish),
+            'S' => Some(Resource::Silk),
+            '!' => Some(Resource::Spices),
+            'v' => Some(Resource::Wine),
+            '.' => Some(Resource::None),
+            _ => None,
         }
     }
 
@@ -321,34 +399,39 @@ pub struct Empire{
 
     //[u32;8] avoids cache misses because its fixed memory
     pub costs: [u32; 8],
+    // value of each resource to this empire when growing with resources, index = Resource
+    pub resource_values: [u32; 11],
+    pub archetype: Option<Archetype>,
 }
 
 impl Empire{
     pub fn new(id: u32, color: u32, size: u32, settings: [u32; 8], cap_index: usize) -> Empire{
-        Empire { id, color, costs: settings, size, cap_index }
+        Empire { id, color, costs: settings, size, cap_index, resource_values: archetype::resource_values(&[]), archetype: None }
     }
 }
 
 
 
-fn string_to_vec<T, F>(map_data: &str, parser: F, fixed_size: usize, default: T) -> Vec<T>
+fn string_to_vec<T, F>(map_data: &str, parser: F, fixed_width: usize, fixed_height: usize, default: T) -> Vec<T>
 where
     F: Fn(char) -> T, 
     T: Clone,
 {
-    let lines: Vec<&str> = map_data.lines().filter(|l| !l.is_empty()).collect();
+    let fixed_size = fixed_width * fixed_height;
+    let lines = rle::rows(map_data, Some(fixed_width));
     let height = lines.len();
-    let width = if height > 0 { lines[0].trim().len() } else { 0 };
+    let width = if height > 0 { lines[0].len() } else { 0 };
     let size = width * height;
 
-    if size != fixed_size {
-        return vec![default; size]
+    if size != fixed_size || width != fixed_width {
+        console_log!("Map data is {}x{} ({} tiles) but {} were expected, using defaults", width, height, size, fixed_size);
+        return vec![default; fixed_size]
     }
 
     let mut result_vector = Vec::with_capacity(size);
 
     for line in lines {
-        for c in line.trim().chars() {
+        for c in line.chars() {
             result_vector.push(parser(c));
         }
     }
@@ -363,40 +446,101 @@ where
 pub struct World {
     width: usize,
     height: usize,
-    tiles: Vec<Terrain>,
-    owners: Vec<u32>,
-    resources: Vec<Resource>,
+    name: String,
+    wrap: WrapMode,
+    tiles: Grid<Terrain>,
+    owners: Grid<u32>,
+    resources: Grid<Resource>,
+    // chunked worlds allocate their grids lazily and their render buffers on first use
+    chunked: bool,
     terrain_buffer: Vec<u32>,
     ownership_buffer: Vec<u32>,
     dist_buffer: Vec<u32>,
+    // whether render_dist_map ever filled dist_buffer, it starts out transparent
+    dist_rendered: bool,
+    components_buffer: Vec<u32>,
     resource_buffer: Vec<u32>,
+    border_buffer: Vec<u32>,
+    composite_buffer: Vec<u32>,
+    // sized by the last render_viewport call, not by the map
+    viewport_buffer: Vec<u32>,
     
-    dist_vector: Vec<u32>,
-    dist_map: Vec<u32>,
+    dist_vector: Grid<u32>,
+    dist_map: Grid<u32>,
     empires: HashMap<u32, Empire>,
+    frontier: Frontier,
+    // auto_grow's search distances, all u32::MAX between calls
+    grow_dist: Grid<u32>,
+
+    replay: Option<Replay>,
+    dirty: DirtyRegions,
+    // (border_color, coastline_color) of the last render_borders, a change needs a full redraw
+    border_style: Option<(Option<u32>, Option<u32>)>,
+    // (layer_mask, ownership alpha) of the last render_composite
+    composite_style: Option<(u32, u32)>,
+    queue_backend: QueueBackend,
+    parallel_growth: bool,
+    brush: Brush,
+    // empire groups of the last parallel auto_grow
+    growth_groups: Vec<Vec<u32>>,
+    // its search state, reset tile by tile like grow_dist
+    growth_scratch: GrowthScratch,
 }
 
 
 
 
-///////
-/// need to add valueMap
+// Rows of resource characters (run-length encoding allowed), all of the same width.
+// Runs are expanded no further than just past `max_width`, a wider sheet can't fit anyway.
+fn parse_resource_sheet(resource_data: &str, max_width: usize) -> Result<(usize, usize, Vec<Resource>), String> {
+    let lines = rle::rows(resource_data, Some(max_width));
+    let height = lines.len();
+    let width = if height > 0 { lines[0].chars().count() } else { 0 };
+    if width == 0 {
+        return Err("resource data is empty".to_string());
+    }
+
+    let mut resources = Vec::with_capacity(width * height);
+    for (y, line) in lines.iter().enumerate() {
+        let mut row_width = 0;
+        for (x, c) in line.chars().enumerate() {
+            let resource = Resource::try_from_char(c)
+                .ok_or_else(|| format!("row {}, column {}: unknown resource character `{}`", y + 1, x + 1, c))?;
+            resources.push(resource);
+            row_width += 1;
+        }
+        if row_width != width {
+            return Err(format!("row {} is {} tiles wide, expected {}", y + 1, row_width, width));
+        }
+    }
+
+    Ok((width, height, resources))
+}
+
 
-#[wasm_bindgen]
 impl World {
-    pub fn new(map_str: &str, value_str: Option<String>) -> World {
-        let lines: Vec<&str> = map_str.lines().filter(|l| !l.is_empty()).collect();
+    // Copies a parsed sheet onto the map at (x, y) and marks that part of the resource layer,
+    // the caller renders it like after import_resource_image
+    fn write_resources(&mut self, x: usize, y: usize, width: usize, height: usize, resources: &[Resource]) {
+        for (row, line) in resources.chunks(width).enumerate() {
+            let start = (y + row) * self.width + x;
+            for (offset, &resource) in line.iter().enumerate() {
+                self.resources.set(start + offset, resource);
+            }
+        }
+
+        self.dirty.mark(Layer::Resources, x, y, x + width - 1, y + height - 1, 0);
+    }
+
+    fn build(map_str: &str, value_str: Option<String>, chunked: bool) -> World {
+        let lines = rle::rows(map_str, None);
         let height = lines.len();
-        let width = if height > 0 { lines[0].trim().len() } else { 0 };
+        let width = if height > 0 { lines[0].len() } else { 0 };
         let size = width * height;
 
-        let dist_vector = vec![u32::MAX; width* height];
-        let dist_map = vec![u32::MAX; size];
-        let empires = HashMap::new();
-
         let mut tiles = Vec::with_capacity(size);
         for line in lines {
-            for c in line.trim().chars() {
+            for c in line.chars() {
                 tiles.push(Terrain::from_char(c));
             }
         }
@@ -404,51 +548,146 @@ impl World {
         // let tiles = string_to_vec(map_str, Terrain::from_char);
 
         let resources = match value_str {
-            Some(resourceData) => string_to_vec(&resourceData, Resource::from_char, size, Resource::None),
+            Some(resource_data) => string_to_vec(&resource_data, Resource::from_char, width, height, Resource::None),
             None => vec![Resource::None; size],
         };
 
+        World::from_grids(width, height, tiles, resources, chunked)
+    }
+
+    fn from_grids(width: usize, height: usize, tiles: Vec<Terrain>, resources: Vec<Resource>, chunked: bool) -> World {
+        let size = width * height;
+        let dist_vector = Grid::new(width, height, u32::MAX, chunked);
+        let dist_map = Grid::new(width, height, u32::MAX, chunked);
+        let empires = HashMap::new();
+
+        // render buffers of a chunked world are allocated by their first render
+        let buffer = |fill: u32| if chunked { Vec::new() } else { vec![fill; size] };
+
         let mut world = World {
             width,
             height,
-            tiles,
-            owners: vec![0; size],
-            resources,
-            terrain_buffer: vec![0xFF000000; size],
-            ownership_buffer: vec![0x00000000; size],
-            dist_buffer: vec![0x0000000; size],
-            resource_buffer: vec![0x00000000; size],
+            name: String::new(),
+            wrap: WrapMode::None,
+            tiles: Grid::from_vec(tiles, width, height, chunked),
+            owners: Grid::new(width, height, 0, chunked),
+            resources: Grid::from_vec(resources, width, height, chunked),
+            chunked,
+            terrain_buffer: buffer(0xFF000000),
+            ownership_buffer: buffer(0x00000000),
+            dist_buffer: buffer(0x0000000),
+            dist_rendered: false,
+            components_buffer: buffer(0x00000000),
+            resource_buffer: buffer(0x00000000),
+            border_buffer: buffer(0x00000000),
+            composite_buffer: buffer(0x00000000),
+            viewport_buffer: Vec::new(),
 
             dist_vector,
             dist_map,
-            empires
+            empires,
+            frontier: Frontier::default(),
+            grow_dist: Grid::Flat(Vec::new()),
+
+            replay: None,
+            dirty: DirtyRegions::new(width, height),
+            border_style: None,
+            composite_style: None,
+            queue_backend: QueueBackend::default(),
+            parallel_growth: false,
+            brush: Brush::default(),
+            growth_groups: Vec::new(),
+            growth_scratch: GrowthScratch::default(),
         };
 
         // Render immediately upon creation
-        world.render_terrain();
+        if !chunked {
+            world.refresh_terrain();
+        }
 
         world
     }
+}
+
+
+///////
+/// need to add valueMap
+
+#[wasm_bindgen]
+impl World {
+    pub fn new(map_str: &str, value_str: Option<String>) -> World {
+        World::build(map_str, value_str, false)
+    }
+
+    /// Same as new, but stores the map in lazily allocated 64x64 chunks
+    /// (uniform chunks such as open ocean hold a single value) and only allocates a
+    /// full-size render buffer when that layer is first rendered.
+    /// Meant for continental-scale maps drawn through render_viewport.
+    pub fn new_chunked(map_str: &str, value_str: Option<String>) -> World {
+        World::build(map_str, value_str, true)
+    }
+
 
     pub fn width(&self) -> usize { self.width }
     pub fn height(&self) -> usize { self.height }
 
+    pub fn is_chunked(&self) -> bool { self.chunked }
 
-    ////loading just resource data
-    pub fn import_resource_data(&mut self, resource_data: String){
-        let lines: Vec<&str> = resource_data.lines().filter(|l| !l.is_empty()).collect();
-        let height = lines.len();
-        let width = if height > 0 { lines[0].trim().len() } else { 0 };
+    /// priority queue used by calc_teritory, djisktra_dist_point and auto_grow
+    /// both backends pop in the same order, so results don't depend on the choice
+    pub fn set_queue_backend(&mut self, backend: QueueBackend) { self.queue_backend = backend; }
+    pub fn queue_backend(&self) -> QueueBackend { self.queue_backend }
 
-        if self.width != width || self.height != height{
-            return;
+    /// bytes held by the map grids and render buffers, to compare flat and chunked storage
+    pub fn storage_bytes(&self) -> usize {
+        let grids = self.tiles.memory_bytes() + self.owners.memory_bytes() + self.resources.memory_bytes()
+            + self.dist_vector.memory_bytes() + self.dist_map.memory_bytes();
+        let buffers = [
+            &self.terrain_buffer, &self.ownership_buffer, &self.dist_buffer, &self.components_buffer,
+            &self.resource_buffer, &self.border_buffer, &self.composite_buffer, &self.viewport_buffer,
+        ].iter().map(|b| b.capacity() * 4).sum::<usize>();
+
+        grids + buffers
+    }
+
+    /// number of allocated 64x64 chunks across the grids of a chunked world
+    pub fn dense_chunk_count(&self) -> usize {
+        self.tiles.dense_chunks() + self.owners.dense_chunks() + self.resources.dense_chunks()
+            + self.dist_vector.dense_chunks() + self.dist_map.dense_chunks()
+    }
+
+    /// frees chunks that became uniform again (e.g. after delete_empire), no-op for flat worlds
+    pub fn compact_storage(&mut self) {
+        self.tiles.compact();
+        self.owners.compact();
+        self.resources.compact();
+        self.dist_vector.compact();
+        self.dist_map.compact();
+    }
+
+
+    ////loading just resource data
+    /// Replaces the resource layer, the sheet must match the map's size
+    pub fn import_resource_data(&mut self, resource_data: String) -> Result<(), String> {
+        let (width, height, resources) = parse_resource_sheet(&resource_data, self.width)?;
+        if self.width != width || self.height != height {
+            return Err(format!("resource data is {}x{} but the map is {}x{}", width, height, self.width, self.height));
         }
 
-        for line in lines {
-            for c in line.trim().chars() {
-                self.resources.push(Resource::from_char(c));
-            }
+        self.write_resources(0, 0, width, height, &resources);
+        Ok(())
+    }
+
+    /// Writes a smaller resource sheet with its top left corner on (x, y), the rest of
+    /// the layer is kept. The sheet has to fit inside the map.
+    pub fn import_resource_data_at(&mut self, resource_data: String, x: usize, y: usize) -> Result<(), String> {
+        let (width, height, resources) = parse_resource_sheet(&resource_data, self.width)?;
+        if x.saturating_add(width) > self.width || y.saturating_add(height) > self.height {
+            return Err(format!("resource data is {}x{} and does not fit at ({}, {}) on a {}x{} map", width, height, x, y, self.width, self.height));
         }
+
+        self.write_resources(x, y, width, height, &resources);
+        Ok(())
     }
 
 
@@ -467,79 +706,91 @@ impl World {
         self.dist_buffer.as_ptr()
     }
 
+    pub fn get_components_buffer_ptr(&self) -> *const u32 {
+        self.components_buffer.as_ptr()
+    }
+
     pub fn get_resource_buffer_ptr(&self) -> *const u32{
         self.resource_buffer.as_ptr()
     }
 
+    pub fn get_border_buffer_ptr(&self) -> *const u32{
+        self.border_buffer.as_ptr()
+    }
+
+    pub fn get_composite_buffer_ptr(&self) -> *const u32{
+        self.composite_buffer.as_ptr()
+    }
+
+    // may move after render_viewport changes the output size, fetch it after every call
+    pub fn get_viewport_buffer_ptr(&self) -> *const u32{
+        self.viewport_buffer.as_ptr()
+    }
+
     // PARALLEL RENDERER
+    // Each renderer only redraws the area changed since its last call and returns it as
+    // [x, y, width, height] (empty when nothing changed), so JS can upload just that rect
+    // with putImageData.
+
+    /// forces the next render of every layer to redraw the whole buffer
+    pub fn invalidate_layers(&mut self) {
+        self.dirty.mark_all();
+    }
     
-    pub fn render_terrain(&mut self) {
-        // Zip the tiles (Read) with the buffer (Write)
-        // par_iter() splits this work across all Web Workers
-        self.terrain_buffer
-            .par_iter_mut()
-            .zip(self.tiles.par_iter())
-            .for_each(|(pixel, tile)| {
-                *pixel = tile.get_color();
-            });
-    }
-
-    pub fn render_ownership(&mut self) {
-        let empires= &self.empires;
+    pub fn render_terrain(&mut self) -> Vec<u32> {
+        self.refresh_terrain();
+        rect(self.dirty.take_drawn(Layer::Terrain))
+    }
 
-        self.ownership_buffer
-            .par_iter_mut()
-            .zip(self.owners.par_iter())
-            .for_each(|(pixel, &owner_id)| {
-                if owner_id == 0 {
-                    *pixel = 0x00000000;
-                } else {
-                    *pixel = match empires.get(&owner_id) {
-                        Some(emp) => emp.color,
-                        None => 0xFFFFFFFF 
-                    };
-                }
-            });
+    pub fn render_ownership(&mut self) -> Vec<u32> {
+        self.refresh_ownership();
+        rect(self.dirty.take_drawn(Layer::Ownership))
     }
 
-    pub fn render_dist_map(&mut self, max_dist_option: Option<u32>) {
+    /// Marks tiles that touch a different owner.
+    /// border_color: None draws each empire's border as a darker shade of its color.
+    /// coastline_color: Some also outlines land tiles next to open water.
+    pub fn render_borders(&mut self, border_color: Option<u32>, coastline_color: Option<u32>) -> Vec<u32> {
+        self.refresh_borders(border_color, coastline_color);
+        rect(self.dirty.take_drawn(Layer::Borders))
+    }
+
+    /// the distance map is recomputed as a whole, so this always redraws the full buffer
+    pub fn render_dist_map(&mut self, max_dist_option: Option<u32>) -> Vec<u32> {
+        let size = self.width * self.height;
+        if size == 0 {
+            return Vec::new();
+        }
+        ensure_buffer(&mut self.dist_buffer, size);
+
         let max_dist_f: f32 = match max_dist_option {
             Some(val) => val as f32,
-            None => {
-                let max_found = self.dist_map
-                    .par_iter()
-                    .filter(|&&d| d != u32::MAX)
-                    .max()
-                    .cloned()
-                    .unwrap_or(1);
-                    
-                max_found as f32
-            }
+            None => self.dist_map.max_except(u32::MAX).unwrap_or(1) as f32,
         };
 
         // parallel pixel drawing
-        self.dist_buffer
-            .par_iter_mut()
-            .zip(self.dist_map.par_iter())
-            .for_each(|(pixel, &dist)| {
-                if dist == u32::MAX {
-                    // Unreachable areas (e.g. Oceans if you can't swim) -> Transparent
-                    *pixel = 0x00000000; 
-                } else {
-                    // Normalize distance 0.0 to 1.0
-                    let t = dist as f32 / max_dist_f;
-                    *pixel = heat_map_color(t);
-                }
-            });
+        let dist_map = &self.dist_map;
+        let full = Bounds { min_x: 0, min_y: 0, max_x: self.width - 1, max_y: self.height - 1 };
+        par_render_rect(&mut self.dist_buffer, self.width, full, |index, pixel| {
+            let dist = dist_map[index];
+            if dist == u32::MAX {
+                // Unreachable areas (e.g. Oceans if you can't swim) -> Transparent
+                *pixel = 0x00000000; 
+            } else {
+                // Normalize distance 0.0 to 1.0
+                let t = dist as f32 / max_dist_f;
+                *pixel = heat_map_color(t);
+            }
+        });
+        self.dist_rendered = true;
+        self.dirty.mark_layer(Layer::Composite);
+
+        rect(Some(full))
     }
 
-    pub fn render_resources(&mut self){
-        self.resource_buffer
-            .par_iter_mut()
-            .zip(self.resources.par_iter())
-            .for_each(|(pixel, resource)| {
-                *pixel = resource.get_color();
-            });
+    pub fn render_resources(&mut self) -> Vec<u32> {
+        self.refresh_resources();
+        rect(self.dirty.take_drawn(Layer::Resources))
     }
 
 
@@ -558,11 +809,12 @@ impl World {
 
         let index = y * self.width + x;
         if index < self.owners.len() {
-            self.owners[index] = empire_id;
+            self.set_owner(index, empire_id);
             self.dist_vector[index] = 0;
         }
 
         if !self.tiles[index].is_liveable() {
+            self.commit_tick();
             return false;
         }
 
@@ -575,10 +827,14 @@ impl World {
         let empire = Empire::new(empire_id, color, size, costs, index);
 
         self.empires.insert(empire_id, empire);
+        if let Some(replay) = self.replay.as_mut() {
+            replay.note_empire(empire_id, color);
+        }
 
         self.calc_teritory(index, empire_id, size);
+        self.commit_tick();
 
-        return true;
+        true
     }
 
     /// updating empire color
@@ -586,6 +842,11 @@ impl World {
         if let Some(empire) = self.empires.get_mut(&empire_id){
             empire.color = color;
         }
+        self.dirty.mark_layer(Layer::Ownership);
+        self.dirty.mark_layer(Layer::Borders);
+        if let Some(replay) = self.replay.as_mut() {
+            replay.note_empire(empire_id, color);
+        }
     }
 
     /// changing empire settings
@@ -608,20 +869,191 @@ impl World {
     pub fn delete_empire(&mut self, empire_id: u32){
         self.empires.remove(&empire_id);
 
-        self.owners.par_iter_mut()
-            .zip(self.dist_vector.par_iter_mut())
-            .for_each(|(owner, dist)| {
-                if *owner == empire_id{
-                    *owner = 0;
-                    *dist = u32::MAX;
-                }
-            });
+        let lost = self.owners.indices_where(|owner| owner == empire_id);
+
+        for index in lost {
+            self.set_owner(index, 0);
+            self.dist_vector[index] = u32::MAX;
+        }
+        self.commit_tick();
     }
 }
 
 
 //////Map Logic Implementation
 impl World{
+    // Buffer refreshers behind render_*, shared with render_composite. Each redraws the stale
+    // part of its layer and leaves the rect for the layer's canvas to fetch.
+    pub(crate) fn refresh_terrain(&mut self) {
+        let Some(bounds) = self.dirty.take(Layer::Terrain) else { return };
+        ensure_buffer(&mut self.terrain_buffer, self.width * self.height);
+        let tiles = &self.tiles;
+
+        // par_render_rect splits the rows across all Web Workers
+        par_render_rect(&mut self.terrain_buffer, self.width, bounds, |index, pixel| {
+            *pixel = tiles[index].get_color();
+        });
+        self.dirty.mark_bounds(Layer::Composite, bounds);
+        self.dirty.drawn(Layer::Terrain, bounds);
+    }
+
+    pub(crate) fn refresh_ownership(&mut self) {
+        let Some(bounds) = self.dirty.take(Layer::Ownership) else { return };
+        ensure_buffer(&mut self.ownership_buffer, self.width * self.height);
+        let empires= &self.empires;
+        let (owners, palette) = Self::displayed_owners(&self.owners, &self.replay);
+
+        par_render_rect(&mut self.ownership_buffer, self.width, bounds, |index, pixel| {
+            let owner_id = owners[index];
+            if owner_id == 0 {
+                *pixel = 0x00000000;
+            } else {
+                *pixel = owner_color(empires, palette, owner_id);
+            }
+        });
+        self.dirty.mark_bounds(Layer::Composite, bounds);
+        self.dirty.drawn(Layer::Ownership, bounds);
+    }
+
+    pub(crate) fn refresh_borders(&mut self, border_color: Option<u32>, coastline_color: Option<u32>) {
+        if self.border_style != Some((border_color, coastline_color)) {
+            self.border_style = Some((border_color, coastline_color));
+            self.dirty.mark_layer(Layer::Borders);
+        }
+        let Some(bounds) = self.dirty.take(Layer::Borders) else { return };
+        ensure_buffer(&mut self.border_buffer, self.width * self.height);
+
+        let width = self.width;
+        let height = self.height;
+        let empires = &self.empires;
+        let tiles = &self.tiles;
+        let (owners, palette) = Self::displayed_owners(&self.owners, &self.replay);
+
+        par_render_rect(&mut self.border_buffer, width, bounds, |index, pixel| {
+            let x = index % width;
+            let y = index / width;
+            let owner_id = owners[index];
+
+            let mut neighbours = [None; 4];
+            if x > 0 { neighbours[0] = Some(index - 1); }
+            if x + 1 < width { neighbours[1] = Some(index + 1); }
+            if y > 0 { neighbours[2] = Some(index - width); }
+            if y + 1 < height { neighbours[3] = Some(index + width); }
+
+            let on_border = owner_id != 0 && neighbours.iter().flatten().any(|&n| owners[n] != owner_id);
+
+            *pixel = if on_border {
+                match border_color {
+                    Some(color) => color,
+                    None => darken_color(owner_color(empires, palette, owner_id), 0.55),
+                }
+            } else {
+                match coastline_color {
+                    Some(color) if tiles[index].is_liveable()
+                        && neighbours.iter().flatten().any(|&n| tiles[n] == Terrain::Water) => color,
+                    _ => 0x00000000,
+                }
+            };
+        });
+        self.dirty.mark_bounds(Layer::Composite, bounds);
+        self.dirty.drawn(Layer::Borders, bounds);
+    }
+
+    pub(crate) fn refresh_resources(&mut self) {
+        let Some(bounds) = self.dirty.take(Layer::Resources) else { return };
+        ensure_buffer(&mut self.resource_buffer, self.width * self.height);
+        let resources = &self.resources;
+
+        par_render_rect(&mut self.resource_buffer, self.width, bounds, |index, pixel| {
+            *pixel = resources[index].get_color();
+        });
+        self.dirty.mark_bounds(Layer::Composite, bounds);
+        self.dirty.drawn(Layer::Resources, bounds);
+    }
+
+    // The owner grid the renderers should draw: the replay view while scrubbing, otherwise the live grid
+    fn displayed_owners<'a>(owners: &'a Grid<u32>, replay: &'a Option<Replay>) -> (&'a Grid<u32>, Option<&'a HashMap<u32, u32>>) {
+        match replay.as_ref().and_then(|r| r.view().map(|view| (view, r.palette()))) {
+            Some((view, palette)) => (view, Some(palette)),
+            None => (owners, None),
+        }
+    }
+
+    // every ownership change goes through here so the replay can record it
+    fn set_owner(&mut self, index: usize, empire_id: u32) {
+        let previous = self.owners[index];
+        self.owners.set(index, empire_id);
+        self.frontier.owner_changed(&self.owners, self.width, self.height, index, previous);
+
+        // the border of both this tile and its neighbours may have moved
+        let (x, y) = (index % self.width, index / self.width);
+        self.dirty.mark(Layer::Ownership, x, y, x, y, 0);
+        self.dirty.mark(Layer::Borders, x, y, x, y, 1);
+
+        if let Some(replay) = self.replay.as_mut() {
+            replay.record(index, empire_id);
+        }
+    }
+
+    // Growth candidates for auto_grow: every empty tile next to an owned one, once per owning neighbour
+    fn frontier_seeds(&self, use_resources: bool) -> Vec<AutoGrowState> {
+        let width = self.width;
+        let height = self.height;
+        let directions = [(0, -1), (0, 1), (-1, 0), (1, 0)];
+
+        self.frontier.entries()
+            .into_par_iter()
+            .flat_map_iter(|(owner, index)| {
+                let x = (index % width) as i32;
+                let y = (index / width) as i32;
+                let current_true_dist = self.dist_vector[index];
+                let empire = self.empires.get(&owner);
+
+                directions.into_iter().filter_map(move |(dx, dy)| {
+                    let (costs, resource_values) = (empire?.costs, empire?.resource_values);
+                    let nx = x + dx;
+                    let ny = y + dy;
+                    if nx < 0 || nx >= width as i32 || ny < 0 || ny >= height as i32 { return None; }
+
+                    let neib_idx = (ny as usize * width) + (nx as usize);
+                    if self.owners[neib_idx] != 0 { return None; }
+
+                    let neib_terrain = self.tiles[neib_idx];
+                    let move_cost = costs[neib_terrain as usize];
+
+                    let current_terrain = self.tiles[index];
+                    let is_transition = current_terrain.is_watery() != neib_terrain.is_watery();
+                    let penalty = if is_transition { costs[1] * 3 } else { 0 };
+
+                    let new_true_cost = current_true_dist.saturating_add(move_cost).saturating_add(penalty);
+
+                    let sort_cost = if use_resources {
+                        let resource_val = resource_values[self.resources[neib_idx] as usize];
+                        // Formula: dist / (1 + value)
+                        new_true_cost / (1 + resource_val)
+                    } else {
+                        new_true_cost
+                    };
+
+                    Some(AutoGrowState {
+                        sort_cost,
+                        true_cost: new_true_cost,
+                        index: neib_idx,
+                        empire_id: owner
+                    })
+                })
+            })
+            .collect()
+    }
+
+    // marks the end of one simulation step for the replay
+    fn commit_tick(&mut self) {
+        if let Some(replay) = self.replay.as_mut() {
+            let owners = &self.owners;
+            replay.commit_tick(|| owners.to_vec());
+        }
+    }
+
     pub fn calc_teritory(
         &mut self,
         start_index: usize,
@@ -635,8 +1067,8 @@ impl World{
 
         let costs = self.empires.get(&empire_id).expect("Empire ID is wrong").costs;
 
-        let mut pq = BinaryHeap::<State>::new();
-        let mut dist_local = vec![u32::MAX; width * height];
+        let mut pq = Queue::<State>::new(self.queue_backend);
+        let mut dist_local = Grid::new(width, height, u32::MAX, self.chunked);
         let directions = [(0, -1), (0, 1), (-1, 0), (1, 0)];
 
         pq.push(State{cost: 0, index: start_index});
@@ -655,7 +1087,7 @@ impl World{
 
             // claim the tyle
             if self.tiles[index].is_liveable(){
-                self.owners[index] = empire_id;
+                self.set_owner(index, empire_id);
                 self.dist_vector[index] = cost;
                 claimed_count += 1;
             }
@@ -698,15 +1130,12 @@ impl World{
 
 #[wasm_bindgen]
 impl World{
-    pub fn djisktra_dist_point(&mut self, start_x: usize, start_y: usize, empire_id: u32, settings: Vec<u32>) {
+    pub fn djisktra_dist_point(&mut self, start_x: usize, start_y: usize, _empire_id: u32, settings: Vec<u32>) {
         let width = self.width;
         let height = self.height;
-        let size = width * height;
         let start_index = start_y * width + start_x;
 
-        for i in 0..size {
-            self.dist_map[i] = u32::MAX;
-        }
+        self.dist_map.fill(u32::MAX);
 
         // Get empire costs
         // let costs = match self.empires.get(&empire_id) {
@@ -724,7 +1153,7 @@ impl World{
             costs[i] = cost;
         }
 
-        let mut pq = BinaryHeap::<State>::new();
+        let mut pq = Queue::<State>::new(self.queue_backend);
         let directions = [(0, -1), (0, 1), (-1, 0), (1, 0)];
 
         pq.push(State { cost: 0, index: start_index });
@@ -884,65 +1313,29 @@ impl World{
 
 
     pub fn auto_grow(&mut self, size: u32, use_resources: bool) {
+        if self.parallel_growth {
+            self.auto_grow_parallel(size, use_resources);
+            return;
+        }
+
         let width = self.width;
         let height = self.height;
 
-        let mut pq = BinaryHeap::new();
+        let mut pq = Queue::<AutoGrowState>::new(self.queue_backend);
         let mut grow_counts: HashMap<u32, u32> = HashMap::new();
         let directions = [(0, -1), (0, 1), (-1, 0), (1, 0)];
 
         // SCAN LOOP for empty tiles
-
-        for index in 0..(width * height) {
-            let owner = self.owners[index];
-            if owner != 0 {
-                let x = (index % width) as i32;
-                let y = (index / width) as i32;
-                
-                let current_true_dist = self.dist_vector[index]; 
-
-                if let Some(empire) = self.empires.get(&owner) {
-                    let costs = empire.costs;
-
-                    for (dx, dy) in directions {
-                        let nx = x + dx;
-                        let ny = y + dy;
-                        if nx < 0 || nx >= width as i32 || ny < 0 || ny >= height as i32 { continue; }
-                        
-                        let neib_idx = (ny as usize * width) + (nx as usize);
-                        
-                        if self.owners[neib_idx] == 0 {
-                            let neib_terrain = self.tiles[neib_idx];
-                            let move_cost = costs[neib_terrain as usize];
-                            
-                            let current_terrain = self.tiles[index];
-                            let is_transition = current_terrain.is_watery() != neib_terrain.is_watery();
-                            let penalty = if is_transition { costs[1] * 3 } else { 0 };
-
-                            let new_true_cost = current_true_dist.saturating_add(move_cost).saturating_add(penalty);
-
-                            let sort_cost = if use_resources {
-                                let resource_val = self.resources[neib_idx].get_value();
-                                // Formula: dist / (1 + value)
-                
-                                new_true_cost / (1 + resource_val)
-                            } else {
-                                new_true_cost
-                            };
-
-                            pq.push(AutoGrowState {
-                                sort_cost,
-                                true_cost: new_true_cost,
-                                index: neib_idx,
-                                empire_id: owner
-                            });
-                        }
-                    }
-                }
-            }
+        for seed in self.frontier_seeds(use_resources) {
+            pq.push(seed);
         }
 
-        let mut local_dist = vec![u32::MAX; width * height];
+        // kept between calls and reset tile by tile, so a small tick doesn't pay for the whole map
+        let mut local_dist = std::mem::replace(&mut self.grow_dist, Grid::Flat(Vec::new()));
+        if local_dist.len() != width * height {
+            local_dist = Grid::new(width, height, u32::MAX, self.chunked);
+        }
+        let mut touched = Vec::new();
 
         // EXPANSION LOOP
         while let Some(AutoGrowState { sort_cost: _, true_cost, index, empire_id }) = pq.pop() {
@@ -957,15 +1350,17 @@ impl World{
             // claim Logic
             if self.owners[index] == 0 {
                 if self.tiles[index].is_liveable() {
-                    self.owners[index] = empire_id;
+                    self.set_owner(index, empire_id);
                     
                     self.dist_vector[index] = true_cost; 
                     local_dist[index] = true_cost;
+                    touched.push(index);
                     
                     *current_growth += 1;
                 }
 
-                let costs = self.empires.get(&empire_id).unwrap().costs;
+                let empire = self.empires.get(&empire_id).unwrap();
+                let (costs, resource_values) = (empire.costs, empire.resource_values);
                 let x = (index % width) as i32;
                 let y = (index / width) as i32;
                 let current_terrain = self.tiles[index];
@@ -986,7 +1381,7 @@ impl World{
                         let new_true_cost = true_cost.saturating_add(move_cost).saturating_add(penalty);
 
                         let new_sort_cost = if use_resources {
-                            let resource_val = self.resources[neib_idx].get_value();
+                            let resource_val = resource_values[self.resources[neib_idx] as usize];
                             new_true_cost / (1 + resource_val)
                         } else {
                             new_true_cost
@@ -994,6 +1389,7 @@ impl World{
 
                         if new_true_cost < local_dist[neib_idx] {
                             local_dist[neib_idx] = new_true_cost;
+                            touched.push(neib_idx);
                             pq.push(AutoGrowState { 
                                 sort_cost: new_sort_cost, 
                                 true_cost: new_true_cost, 
@@ -1005,6 +1401,14 @@ impl World{
                 }
             }
         }
+
+        for index in touched {
+            local_dist.set(index, u32::MAX);
+        }
+        local_dist.compact();
+        self.grow_dist = local_dist;
+
+        self.commit_tick();
     }
 
 }
@@ -1013,6 +1417,7 @@ impl World{
 // Implementing the painting options
 #[wasm_bindgen]
 impl World {
+    /// One stamp of the brush, see set_brush_shape and set_brush_falloff
     pub fn paint_terrain_brush(
         &mut self, 
         center_x: i32, 
@@ -1020,46 +1425,12 @@ impl World {
         diameter: i32, 
         terrain_val: char,
     ) {
-        let width = self.width as i32;
-        let height = self.height as i32;
-
-        // 1. Convert char to Enum
         let terrain_type = Terrain::from_char(terrain_val.to_ascii_uppercase());
-        
-        // Get Color
-        let color = terrain_type.get_color();
-
-        // 2. Calculate Radius from Diameter
-        // Integer division: 5 / 2 = 2.
-        // This ensures the brush is centered on the mouse pixel.
-        let radius = diameter / 2;
-        let radius_sq = radius * radius;
-
-        // 3. Bounding Box Optimization
-        let min_x = (center_x - radius).max(0);
-        let max_x = (center_x + radius).min(width - 1);
-        let min_y = (center_y - radius).max(0);
-        let max_y = (center_y + radius).min(height - 1);
-
-        for y in min_y..=max_y {
-            for x in min_x..=max_x {
-                let dx = x - center_x;
-                let dy = y - center_y;
-
-                // 4. Circle Check
-                if dx * dx + dy * dy <= radius_sq {
-                    let index = (y * width + x) as usize;
-
-                    // Update Logical Data
-                    self.tiles[index] = terrain_type;
-
-                    // Update Visual Buffer
-                    self.terrain_buffer[index] = color;
-                }
-            }
-        }
+        let tiles = self.brush_tiles(center_x, center_y, diameter, false);
+        self.draw_terrain(&tiles, terrain_type);
     }
 
+    /// Like paint_terrain_brush, only on liveable tiles and thinned out by set_brush_density
     pub fn paint_resource_brush(
         &mut self, 
         center_x: i32, 
@@ -1067,72 +1438,38 @@ impl World {
         diameter: i32, 
         resource_val: char,
     ) {
-        // console_log!("ENTERED BRUSH RESOURCES with resource_val: {}", resource_val);
-
-        let tiles = &self.tiles;
-
-        let width = self.width as i32;
-        let height = self.height as i32;
-
         let resource_type = Resource::from_char(resource_val);
-        
-        let color = resource_type.get_color();
-
-        let radius = diameter / 2;
-        let radius_sq = radius * radius;
-
-        let min_x = (center_x - radius).max(0);
-        let max_x = (center_x + radius).min(width - 1);
-        let min_y = (center_y - radius).max(0);
-        let max_y = (center_y + radius).min(height - 1);
-
-        for y in min_y..=max_y {
-            for x in min_x..=max_x {
-                let dx = x - center_x;
-                let dy = y - center_y;
-
-                if dx * dx + dy * dy <= radius_sq{
-                    let index = (y * width + x) as usize;
-
-                    if !tiles[index].is_liveable() { continue;}
-
-                    self.resources[index] = resource_type;
-                    self.resource_buffer[index] = color;
-                }
-            }
-        }
+        let tiles = self.brush_tiles(center_x, center_y, diameter, true);
+        self.draw_resource(&tiles, resource_type);
     }
 }
 
 
-// send the mapString of the tiles
+// send the mapString of the tiles, `compressed` writes run-length encoded rows (`120W3P`)
 #[wasm_bindgen]
 impl World {
-    pub fn export_map_to_string(&self) -> String {
-        let capacity = self.width * self.height + self.height;
-        let mut output = String::with_capacity(capacity);
-
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let index = y * self.width + x;
-                output.push(self.tiles[index].to_char());
-            }
-            output.push('\n');
-        }
+    pub fn export_map_to_string(&self, compressed: bool) -> String {
+        self.export_layer(compressed, |index| self.tiles[index].to_char())
+    }
 
-        output
+    pub fn export_resource_to_string(&self, compressed: bool) -> String{
+        self.export_layer(compressed, |index| self.resources[index].to_char())
     }
+}
 
-    pub fn export_resource_to_string(&self) -> String{
-        let capacity = self.width * self.height + self.height;
+impl World {
+    fn export_layer(&self, compressed: bool, to_char: impl Fn(usize) -> char) -> String {
+        let capacity = if compressed { self.height * 16 } else { self.width * self.height + self.height };
         let mut output = String::with_capacity(capacity);
 
-        for y in 0..self.height{
-            for x in 0..self.width{
-                let index = y * self.width + x;
-                output.push(self.resources[index].to_char());
+        for y in 0..self.height {
+            let row = (0..self.width).map(|x| to_char(y * self.width + x));
+            if compressed {
+                rle::push_row(&mut output, row);
+            } else {
+                output.extend(row);
+                output.push('\n');
             }
-            output.push('\n');
         }
 
         output
@@ -0,0 +1,529 @@
+// Self-describing text map format, version 2.
+//
+//   version: 2
+//   name: Europe
+//   width: 284
+//   height: 212
+//   wrap: none                 none | horizontal | vertical | both
+//
+//   [terrain]
+//   WWWPPPMM...                one row per line, Terrain chars, '?' = Unknown
+//
+//   [resources]                optional, Resource chars, '.' = no resource
+//   ...g..w...
+//
+//   [empires]                  optional, one capital per line
+//   # id x y color size cost_0 .. cost_7 [archetype]
+//   1 120 45 0xFF0000FF 20 9999 25 10 15 80 60 20 100
+//   2 30 80 0xFFFF0000 20 9999 8 8 20 100 80 30 100 seafaring
+//
+// Blank lines and lines starting with '#' are skipped outside the grids.
+// Text without a `version:` line is read as the old bare terrain grid.
+
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+use crate::utlis::{MAX_TILES, map_size};
+use crate::{Archetype, Resource, Terrain, World};
+
+pub const MAP_FORMAT_VERSION: u32 = 2;
+
+/// Edge behaviour stored with the map
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    #[default]
+    None = 0,
+    Horizontal = 1,
+    Vertical = 2,
+    Both = 3,
+}
+
+impl WrapMode {
+    fn parse(value: &str) -> Option<WrapMode> {
+        match value {
+            "none" => Some(WrapMode::None),
+            "horizontal" => Some(WrapMode::Horizontal),
+            "vertical" => Some(WrapMode::Vertical),
+            "both" => Some(WrapMode::Both),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            WrapMode::None => "none",
+            WrapMode::Horizontal => "horizontal",
+            WrapMode::Vertical => "vertical",
+            WrapMode::Both => "both",
+        }
+    }
+}
+
+/// Parse failure, line and column are 1-based
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+fn error<T>(line: usize, column: usize, message: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError { line, column, message: message.into() })
+}
+
+pub struct EmpireSpec {
+    pub line: usize,
+    pub id: u32,
+    pub x: usize,
+    pub y: usize,
+    pub color: u32,
+    pub size: u32,
+    pub costs: [u32; 8],
+    pub archetype: Option<Archetype>,
+}
+
+pub struct MapFile {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub wrap: WrapMode,
+    pub tiles: Vec<Terrain>,
+    pub resources: Option<Vec<Resource>>,
+    pub empires: Vec<EmpireSpec>,
+}
+
+// Lines with their 1-based numbers, '\r' already stripped by lines()
+struct Lines<'a> {
+    lines: Vec<&'a str>,
+    next: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Lines<'a> {
+        Lines { lines: text.lines().collect(), next: 0 }
+    }
+
+    fn peek(&self) -> Option<(usize, &'a str)> {
+        self.lines.get(self.next).map(|&line| (self.next + 1, line))
+    }
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        let line = self.peek();
+        self.next += 1;
+        line
+    }
+
+    // past the last line, where missing content is reported
+    fn end(&self) -> usize {
+        self.lines.len() + 1
+    }
+
+    fn at_section(&self) -> bool {
+        self.peek().is_some_and(|(_, line)| line.trim_start().starts_with('['))
+    }
+}
+
+fn is_skipped(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#')
+}
+
+impl MapFile {
+    pub fn parse(text: &str) -> Result<MapFile, ParseError> {
+        let first = text.lines().find(|line| !is_skipped(line)).unwrap_or("");
+        if first.trim_start().starts_with("version:") {
+            MapFile::parse_v2(text)
+        } else {
+            MapFile::parse_bare(text)
+        }
+    }
+
+    // the old format: terrain rows only, the first row sets the width
+    fn parse_bare(text: &str) -> Result<MapFile, ParseError> {
+        let mut lines = Lines::new(text);
+        let mut width = None;
+        let mut rows = Vec::new();
+
+        while let Some((number, line)) = lines.next() {
+            let row = line.trim();
+            if !row.is_empty() {
+                let width = *width.get_or_insert(row.chars().count());
+                rows.push(parse_row(number, line, width, Terrain::try_from_char, "terrain")?);
+            }
+        }
+
+        let width = width.unwrap_or(0);
+        let height = rows.len();
+        Ok(MapFile {
+            name: String::new(),
+            width,
+            height,
+            wrap: WrapMode::None,
+            tiles: rows.concat(),
+            resources: None,
+            empires: Vec::new(),
+        })
+    }
+
+    fn parse_v2(text: &str) -> Result<MapFile, ParseError> {
+        let mut lines = Lines::new(text);
+        let mut version = None;
+        let mut name = String::new();
+        let mut width = None;
+        let mut height = None;
+        let mut wrap = WrapMode::None;
+
+        // header: `key: value` lines up to the first section
+        while !lines.at_section() {
+            let Some((number, line)) = lines.next() else { break };
+            if is_skipped(line) { continue; }
+
+            let Some((key, value)) = line.split_once(':') else {
+                return error(number, 1, "expected `key: value` or a [section]");
+            };
+            let value_column = key.len() + 2 + (value.len() - value.trim_start().len());
+            let value = value.trim();
+            let number_value = || value.parse::<usize>()
+                .or_else(|_| error(number, value_column, format!("`{}` is not a number", value)));
+
+            match key.trim() {
+                "version" => version = Some((number, value_column, number_value()?)),
+                "name" => name = value.to_string(),
+                "width" => width = Some((number, value_column, number_value()?)),
+                "height" => height = Some((number, value_column, number_value()?)),
+                "wrap" => match WrapMode::parse(value) {
+                    Some(mode) => wrap = mode,
+                    None => return error(number, value_column, format!("unknown wrap mode `{}`, expected none, horizontal, vertical or both", value)),
+                },
+                other => return error(number, 1, format!("unknown header key `{}`", other)),
+            }
+        }
+
+        // missing headers are reported where the header ends
+        let end = lines.peek().map_or(lines.end(), |(number, _)| number);
+        match version {
+            Some((_, _, v)) if v == MAP_FORMAT_VERSION as usize => {}
+            Some((number, column, v)) => return error(number, column, format!("unsupported map version {}", v)),
+            None => return error(end, 1, "missing `version:` header"),
+        }
+        let Some((width_line, width_column, width)) = width else { return error(end, 1, "missing `width:` header") };
+        let Some((height_line, height_column, height)) = height else { return error(end, 1, "missing `height:` header") };
+        // checked before any grid is read, reported on the later of the two headers
+        if map_size(width as u64, height as u64).is_none() {
+            let (line, column) = if height_line > width_line { (height_line, height_column) } else { (width_line, width_column) };
+            return error(line, column, format!("a {}x{} map is empty or larger than {} tiles", width, height, MAX_TILES));
+        }
+
+        let mut tiles = None;
+        let mut resources = None;
+        let mut empires = Vec::new();
+
+        while let Some((number, line)) = lines.next() {
+            if is_skipped(line) { continue; }
+
+            match line.trim() {
+                "[terrain]" if tiles.is_none() => {
+                    tiles = Some(parse_grid(&mut lines, number, width, height, Terrain::try_from_char, "terrain")?);
+                }
+                "[resources]" if resources.is_none() => {
+                    resources = Some(parse_grid(&mut lines, number, width, height, Resource::try_from_char, "resource")?);
+                }
+                "[empires]" => {
+                    while !lines.at_section() {
+                        let Some((number, line)) = lines.next() else { break };
+                        if is_skipped(line) { continue; }
+                        empires.push(parse_empire(number, line, width, height)?);
+                    }
+                }
+                "[terrain]" | "[resources]" => return error(number, 1, format!("duplicate section {}", line.trim())),
+                other => return error(number, 1, format!("unknown section `{}`", other)),
+            }
+        }
+
+        let Some(tiles) = tiles else { return error(lines.end(), 1, "missing [terrain] section") };
+
+        Ok(MapFile { name, width, height, wrap, tiles, resources, empires })
+    }
+}
+
+// One grid row, exactly `width` chars once surrounding whitespace is trimmed
+fn parse_row<T>(number: usize, line: &str, width: usize, parse: fn(char) -> Option<T>, what: &str) -> Result<Vec<T>, ParseError> {
+    let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+    let row = line.trim();
+
+    let mut values = Vec::with_capacity(width);
+    for (i, c) in row.chars().enumerate() {
+        let column = indent + i + 1;
+        if i >= width {
+            return error(number, column, format!("row is longer than the map width {}", width));
+        }
+        match parse(c) {
+            Some(value) => values.push(value),
+            None => return error(number, column, format!("unknown {} character `{}`", what, c)),
+        }
+    }
+
+    if values.len() < width {
+        return error(number, indent + values.len() + 1, format!("row has {} tiles, the map width is {}", values.len(), width));
+    }
+    Ok(values)
+}
+
+fn parse_grid<T>(lines: &mut Lines, section_line: usize, width: usize, height: usize, parse: fn(char) -> Option<T>, what: &str) -> Result<Vec<T>, ParseError> {
+    // grows with the rows actually read
+    let mut grid = Vec::new();
+    let mut rows = 0;
+    let mut last_line = section_line;
+
+    while rows < height && !lines.at_section() {
+        let Some((number, line)) = lines.next() else { break };
+        last_line = number;
+        if line.trim().is_empty() { continue; }
+
+        grid.extend(parse_row(number, line, width, parse, what)?);
+        rows += 1;
+    }
+
+    if rows < height {
+        return error(last_line + 1, 1, format!("{} grid has {} rows, the map height is {}", what, rows, height));
+    }
+    Ok(grid)
+}
+
+fn parse_empire(number: usize, line: &str, width: usize, height: usize) -> Result<EmpireSpec, ParseError> {
+    // (column, field) pairs
+    let mut fields = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                fields.push((line[..s].chars().count() + 1, &line[s..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    if fields.len() != 13 && fields.len() != 14 {
+        return error(number, 1, format!("expected `id x y color size`, 8 costs and an optional archetype, found {} fields", fields.len()));
+    }
+
+    let number_at = |(column, field): (usize, &str)| -> Result<u32, ParseError> {
+        let parsed = match field.strip_prefix("0x").or_else(|| field.strip_prefix("0X")) {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => field.parse::<u32>(),
+        };
+        parsed.or_else(|_| error(number, column, format!("`{}` is not a number", field)))
+    };
+
+    let id = number_at(fields[0])?;
+    if id == 0 {
+        return error(number, fields[0].0, "empire id 0 is reserved for unowned tiles");
+    }
+    let x = number_at(fields[1])? as usize;
+    let y = number_at(fields[2])? as usize;
+    if x >= width || y >= height {
+        return error(number, fields[1].0, format!("capital ({}, {}) is outside the {}x{} map", x, y, width, height));
+    }
+
+    let mut costs = [0; 8];
+    for (cost, &field) in costs.iter_mut().zip(&fields[5..]) {
+        *cost = number_at(field)?;
+    }
+    let archetype = match fields.get(13) {
+        Some(&(column, name)) => match Archetype::from_name(name) {
+            Some(archetype) => Some(archetype),
+            None => return error(number, column, format!("unknown archetype `{}`", name)),
+        },
+        None => None,
+    };
+
+    Ok(EmpireSpec {
+        line: number,
+        id,
+        x,
+        y,
+        color: number_at(fields[3])?,
+        size: number_at(fields[4])?,
+        costs,
+        archetype,
+    })
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Builds a world from a map file (see the format at the top of mapfile.rs) or a bare
+    /// terrain grid. Errors name the line and column of the problem.
+    pub fn from_map_text(text: &str) -> Result<World, String> {
+        let map = MapFile::parse(text).map_err(|e| e.to_string())?;
+        let resources = map.resources.unwrap_or_else(|| vec![Resource::None; map.width * map.height]);
+
+        let mut world = World::from_grids(map.width, map.height, map.tiles, resources, false);
+        world.name = map.name;
+        world.wrap = map.wrap;
+
+        for empire in map.empires {
+            if world.empires.contains_key(&empire.id) {
+                return Err(format!("line {}, column 1: empire {} is defined twice", empire.line, empire.id));
+            }
+            if !world.add_empire(empire.x, empire.y, empire.id, empire.color, empire.size, empire.costs.to_vec()) {
+                return Err(format!("line {}, column 1: capital of empire {} is not on land", empire.line, empire.id));
+            }
+            if let Some(archetype) = empire.archetype {
+                world.set_archetype(empire.id, archetype);
+            }
+        }
+
+        Ok(world)
+    }
+
+    /// Writes the map in the version 2 text format. The resources section is left out when
+    /// there are none, empires are written with their capital, size, costs and archetype.
+    pub fn export_map_text(&self) -> String {
+        let mut output = String::with_capacity((self.width + 1) * self.height * 2 + 256);
+        output.push_str(&format!("version: {}\n", MAP_FORMAT_VERSION));
+        output.push_str(&format!("name: {}\n", self.name.replace(['\r', '\n'], " ")));
+        output.push_str(&format!("width: {}\n", self.width));
+        output.push_str(&format!("height: {}\n", self.height));
+        output.push_str(&format!("wrap: {}\n", self.wrap.name()));
+
+        output.push_str("\n[terrain]\n");
+        output.push_str(&self.export_map_to_string(false));
+
+        if (0..self.resources.len()).any(|i| self.resources[i] != Resource::None) {
+            output.push_str("\n[resources]\n");
+            output.push_str(&self.export_resource_to_string(false));
+        }
+
+        if !self.empires.is_empty() {
+            output.push_str("\n[empires]\n# id x y color size cost_0 .. cost_7 [archetype]\n");
+            let mut ids: Vec<&u32> = self.empires.keys().collect();
+            ids.sort();
+            for id in ids {
+                let empire = &self.empires[id];
+                let (x, y) = (empire.cap_index % self.width, empire.cap_index / self.width);
+                let costs: Vec<String> = empire.costs.iter().map(|c| c.to_string()).collect();
+                let archetype = empire.archetype.map_or(String::new(), |a| format!(" {}", a.name()));
+                output.push_str(&format!("{} {} {} 0x{:08X} {} {}{}\n", empire.id, x, y, empire.color, empire.size, costs.join(" "), archetype));
+            }
+        }
+
+        output
+    }
+
+    pub fn name(&self) -> String { self.name.clone() }
+    pub fn set_name(&mut self, name: String) { self.name = name; }
+
+    pub fn wrap_mode(&self) -> WrapMode { self.wrap }
+    pub fn set_wrap_mode(&mut self, wrap: WrapMode) { self.wrap = wrap; }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "version: 2\nname: Test\nwidth: 4\nheight: 2\nwrap: horizontal\n";
+
+    fn parse_error(text: &str) -> (usize, usize, String) {
+        match MapFile::parse(text) {
+            Ok(_) => panic!("parsed:\n{}", text),
+            Err(e) => (e.line, e.column, e.message),
+        }
+    }
+
+    fn position(text: &str) -> (usize, usize) {
+        let (line, column, _) = parse_error(text);
+        (line, column)
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let text = format!("{}\n[terrain]\nWPPM\nDFIR\n\n[resources]\ng..w\n....\n\n[empires]\n# comment\n3 1 0 0xFF0000FF 20 9999 25 10 15 80 60 20 100 seafaring\n", HEADER);
+        let map = MapFile::parse(&text).unwrap();
+
+        assert_eq!((map.name.as_str(), map.width, map.height, map.wrap), ("Test", 4, 2, WrapMode::Horizontal));
+        assert_eq!(map.tiles[..4], [Terrain::Water, Terrain::Plain, Terrain::Plain, Terrain::Mountain]);
+        assert_eq!(map.tiles[7], Terrain::River);
+        let resources = map.resources.unwrap();
+        assert_eq!((resources[0], resources[3], resources[4]), (Resource::Gold, Resource::Wheat, Resource::None));
+
+        let empire = &map.empires[0];
+        assert_eq!((empire.line, empire.id, empire.x, empire.y, empire.color), (17, 3, 1, 0, 0xFF0000FF));
+        assert_eq!(empire.costs, [9999, 25, 10, 15, 80, 60, 20, 100]);
+        assert!(empire.archetype.is_some());
+    }
+
+    #[test]
+    fn bare_grid_takes_the_width_of_its_first_row() {
+        let map = MapFile::parse("\nWWP\nPMD\n").unwrap();
+        assert_eq!((map.width, map.height, map.wrap), (3, 2, WrapMode::None));
+        assert!(map.resources.is_none() && map.empires.is_empty());
+
+        assert_eq!(parse_error("WWP\nPM\n"), (2, 3, "row has 2 tiles, the map width is 3".to_string()));
+    }
+
+    #[test]
+    fn header_errors_point_at_the_value() {
+        let (line, column, message) = parse_error("version: 2\nwidth:  four\n");
+        assert_eq!((line, column), (2, 9));
+        assert!(message.contains("`four` is not a number"), "{}", message);
+
+        assert_eq!(position("version: 3\n"), (1, 10));
+        assert_eq!(position("version: 2\nwrap: sideways\n"), (2, 7));
+        assert_eq!(position("version: 2\nsize: 4\n"), (2, 1));
+        assert_eq!(parse_error("version: 2\nwidth: 4\n[terrain]\n"), (3, 1, "missing `height:` header".to_string()));
+    }
+
+    #[test]
+    fn oversized_maps_are_rejected_before_reading_the_grid() {
+        let (line, column, message) = parse_error("version: 2\nwidth: 100000\nheight: 100000\n[terrain]\nW\n");
+        assert_eq!((line, column), (3, 9));
+        assert!(message.contains("larger than"), "{}", message);
+
+        assert_eq!(position("version: 2\nwidth: 0\nheight: 5\n"), (3, 9));
+    }
+
+    #[test]
+    fn grid_errors_point_at_the_tile() {
+        // the row is indented by two spaces, columns count them
+        let (line, column, message) = parse_error(&format!("{}[terrain]\nWPPM\n  WPXM\n", HEADER));
+        assert_eq!((line, column), (8, 5));
+        assert_eq!(message, "unknown terrain character `X`");
+
+        assert_eq!(position(&format!("{}[terrain]\nWPPMP\nWPPM\n", HEADER)), (7, 5));
+        assert_eq!(position(&format!("{}[terrain]\nWPPM\n[resources]\n....\n", HEADER)), (8, 1));
+        assert_eq!(position(&format!("{}[terrain]\nWPPM\nWPPM\n[terrain]\n", HEADER)), (9, 1));
+        assert_eq!(position(&format!("{}[terrain]\nWPPM\nWPPM\n[rivers]\n", HEADER)), (9, 1));
+        assert_eq!(parse_error(HEADER), (6, 1, "missing [terrain] section".to_string()));
+    }
+
+    #[test]
+    fn empire_errors_point_at_the_field() {
+        let empires = |line: &str| format!("{}[terrain]\nWPPM\nWPPM\n[empires]\n{}\n", HEADER, line);
+
+        assert_eq!(position(&empires("1 1 0 0xFF 20 1 2 3 4 5 6 7")), (10, 1));
+        assert_eq!(position(&empires("0 1 0 0xFF 20 1 2 3 4 5 6 7 8")), (10, 1));
+        assert_eq!(position(&empires("1 4 0 0xFF 20 1 2 3 4 5 6 7 8")), (10, 3));
+        assert_eq!(position(&empires("1 1 0 0xZZ 20 1 2 3 4 5 6 7 8")), (10, 7));
+        assert_eq!(position(&empires("1 1 0 0xFF 20 1 2 3 4 5 6 7 8 pirate")), (10, 31));
+    }
+
+    #[test]
+    fn world_reports_duplicate_empires_and_capitals_in_water() {
+        let world = |empires: &str| World::from_map_text(&format!("{}[terrain]\nWPPM\nWPPM\n[empires]\n{}", HEADER, empires));
+
+        let duplicate = "1 1 0 0xFF 20 1 2 3 4 5 6 7 8\n1 2 0 0xFF 20 1 2 3 4 5 6 7 8\n";
+        assert_eq!(world(duplicate).err().unwrap(), "line 11, column 1: empire 1 is defined twice");
+        assert_eq!(world("2 0 1 0xFF 20 1 2 3 4 5 6 7 8\n").err().unwrap(), "line 10, column 1: capital of empire 2 is not on land");
+        assert!(world("2 1 1 0xFF 20 1 2 3 4 5 6 7 8\n").is_ok());
+    }
+}
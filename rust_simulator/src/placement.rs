@@ -0,0 +1,143 @@
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::brush::mix;
+use crate::utlis::INTI_COSTS;
+use crate::World;
+
+// Candidates drawn per capital, the farthest of them wins (best-candidate sampling)
+const SAMPLES: u64 = 12;
+
+// Candidates this close (in squared distance) to the farthest one still count as spread out
+// enough to be picked for their resources instead
+const SHORTLIST: f64 = 0.81;
+
+/// Distinct opaque 0xAABBGGRR colour for the n-th generated empire, hues a golden angle apart
+pub fn generated_color(n: u32) -> u32 {
+    let hue = (n as f32 * 137.507_77) % 360.0;
+    let (saturation, value) = (0.75, if n.is_multiple_of(2) { 0.95 } else { 0.75 });
+
+    let c = value * saturation;
+    let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let channel = |v: f32| (((v + value - c) * 255.0).round() as u32).min(0xFF);
+    0xFF000000 | (channel(b) << 16) | (channel(g) << 8) | channel(r)
+}
+
+impl World {
+    /// Resource value inside the square of `radius` around each tile, from a summed-area table.
+    /// u64, a map full of Gold passes u32::MAX well within MAX_TILES.
+    fn resource_windows(&self, radius: usize) -> Vec<u64> {
+        let (width, height) = (self.width, self.height);
+        let mut table = vec![0u64; (width + 1) * (height + 1)];
+        for y in 0..height {
+            let mut row = 0;
+            for x in 0..width {
+                row += self.resources[y * width + x].get_value() as u64;
+                table[(y + 1) * (width + 1) + x + 1] = table[y * (width + 1) + x + 1] + row;
+            }
+        }
+
+        (0..width * height).into_par_iter().map(|index| {
+            let (x, y) = (index % width, index / width);
+            let (x0, y0) = (x.saturating_sub(radius), y.saturating_sub(radius));
+            let (x1, y1) = ((x + radius + 1).min(width), (y + radius + 1).min(height));
+            // subtracted first, each partial result is itself a window sum and can't wrap
+            table[y1 * (width + 1) + x1] - table[y0 * (width + 1) + x1]
+                - table[y1 * (width + 1) + x0] + table[y0 * (width + 1) + x0]
+        }).collect()
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Adds `n` empires on free liveable tiles, spread out by best-candidate sampling: for each
+    /// capital a few tiles are drawn from `seed` and the one farthest from every capital so far
+    /// (existing ones included) wins. Plain farthest-point sampling would push them all to the
+    /// map's corners and smallest islands instead.
+    ///
+    /// With `balance_radius`, the winner is instead the drawn tile among the nearly farthest
+    /// ones whose resource value within that radius is closest to the map's average, so starts
+    /// are similarly rich. Capitals are at least `min_distance` tiles apart. Each empire is
+    /// placed before the next capital is drawn, so tiles it grew over are not drawn again.
+    /// When the draws allow none, every free tile is considered, and if none is far enough it
+    /// is an error. The empires placed until then are kept, the error lists them.
+    ///
+    /// The empires get the next free ids, generated colours and the default costs.
+    /// Returns their ids.
+    pub fn auto_place_empires(&mut self, n: u32, min_distance: u32, seed: u64, size: u32, balance_radius: Option<u32>) -> Result<Vec<u32>, String> {
+        let width = self.width;
+        let candidates: Vec<usize> = (0..width * self.height)
+            .filter(|&i| self.tiles[i].is_liveable() && self.owners[i] == 0)
+            .collect();
+        if candidates.len() < n as usize {
+            return Err(format!("only {} free liveable tiles for {} empires", candidates.len(), n));
+        }
+
+        let distance = |a: usize, b: usize| {
+            let (dx, dy) = ((a % width) as i64 - (b % width) as i64, (a / width) as i64 - (b / width) as i64);
+            (dx * dx + dy * dy) as u64
+        };
+        // squared distance from each candidate to its nearest capital
+        let mut nearest = vec![u64::MAX; candidates.len()];
+        let update = |nearest: &mut [u64], capital: usize| {
+            nearest.par_iter_mut().zip(&candidates).for_each(|(d, &c)| *d = (*d).min(distance(c, capital)));
+        };
+        for empire in self.empires.values() {
+            update(&mut nearest, empire.cap_index);
+        }
+
+        let windows = balance_radius.map(|radius| {
+            let all = self.resource_windows(radius as usize);
+            let values: Vec<u64> = candidates.iter().map(|&c| all[c]).collect();
+            let target = values.iter().map(|&v| v as f64).sum::<f64>() / values.len().max(1) as f64;
+            (values, target)
+        });
+        let tie = |c: usize| mix(seed ^ c as u64);
+        let min_squared = min_distance as u64 * min_distance as u64;
+
+        let first_id = self.empires.keys().max().map_or(1, |&id| id + 1);
+        let mut ids = Vec::with_capacity(n as usize);
+        for step in 0..n as u64 {
+            // candidates still free and far enough from every capital, earlier empires may
+            // have grown over some of them
+            let open = |i: usize| self.owners[candidates[i]] == 0 && nearest[i] >= min_squared.max(1);
+            let drawn: Vec<usize> = (0..SAMPLES)
+                .map(|k| (mix(seed ^ (step << 32) ^ k) % candidates.len() as u64) as usize)
+                .filter(|&i| open(i))
+                .collect();
+            // none of the draws does, so look through all of them before giving up
+            let pool: Vec<usize> = if drawn.is_empty() { (0..candidates.len()).filter(|&i| open(i)).collect() } else { drawn };
+            let Some(farthest) = pool.iter().map(|&i| nearest[i]).max() else {
+                return Err(format!("only {} of {} capitals fit at least {} tiles apart, empires {:?} were placed", ids.len(), n, min_distance, ids));
+            };
+
+            let floor = ((farthest as f64 * SHORTLIST) as u64).max(min_squared).max(1);
+            let shortlist = pool.into_iter().filter(|&i| nearest[i] >= floor);
+            let pick = match &windows {
+                Some((values, target)) => shortlist.min_by(|&a, &b| {
+                    (values[a] as f64 - target).abs().total_cmp(&(values[b] as f64 - target).abs())
+                        .then(tie(candidates[a]).cmp(&tie(candidates[b])))
+                }),
+                None => shortlist.max_by(|&a, &b| nearest[a].cmp(&nearest[b]).then(tie(candidates[b]).cmp(&tie(candidates[a])))),
+            };
+
+            let pick = candidates[pick.expect("farthest is in the shortlist")];
+            let id = first_id + ids.len() as u32;
+            if !self.add_empire(pick % width, pick / width, id, generated_color(id), size, INTI_COSTS.to_vec()) {
+                return Err(format!("could not place empire {} at ({}, {}), empires {:?} were placed", id, pick % width, pick / width, ids));
+            }
+            ids.push(id);
+            update(&mut nearest, pick);
+        }
+
+        Ok(ids)
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BinaryHeap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{AutoGrowState, State};
+
+/// Priority queue used by the Dijkstra searches
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum QueueBackend {
+    #[default]
+    BinaryHeap = 0,
+    /// Dial-style bucket queue keyed on the integer cost
+    Bucket = 1,
+}
+
+/// Search states sit in the bucket of their integer priority.
+/// `Ord` must put lower keys first (greater), like the max-heap ordering of the states.
+pub trait QueueItem: Ord + Copy {
+    fn key(&self) -> u32;
+}
+
+impl QueueItem for State {
+    fn key(&self) -> u32 { self.cost }
+}
+
+impl QueueItem for AutoGrowState {
+    fn key(&self) -> u32 { self.sort_cost }
+}
+
+/// Queue picked by `QueueBackend`, the searches only see push/pop
+pub enum Queue<S> {
+    Heap(BinaryHeap<S>),
+    Bucket(BucketQueue<S>),
+}
+
+impl<S: QueueItem> Queue<S> {
+    pub fn new(backend: QueueBackend) -> Queue<S> {
+        match backend {
+            QueueBackend::BinaryHeap => Queue::Heap(BinaryHeap::new()),
+            QueueBackend::Bucket => Queue::Bucket(BucketQueue::new()),
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, item: S) {
+        match self {
+            Queue::Heap(heap) => heap.push(item),
+            Queue::Bucket(buckets) => buckets.push(item),
+        }
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<S> {
+        match self {
+            Queue::Heap(heap) => heap.pop(),
+            Queue::Bucket(buckets) => buckets.pop(),
+        }
+    }
+}
+
+// Width of the bucket window, terrain steps are 10-100 (a few hundred with the water penalty)
+const WINDOW: u32 = 1 << 12;
+
+/// Bucket queue over a sliding window of WINDOW consecutive keys.
+///
+/// Keys outside the window wait in an overflow heap; once the window runs dry it
+/// jumps to the smallest overflow key. Keys below the window are allowed (auto_grow's
+/// resource-scaled costs aren't monotone), they are served from the overflow first.
+/// Items sharing a bucket are popped in `Ord` order, so the pop sequence is
+/// the same as a BinaryHeap's.
+pub struct BucketQueue<S> {
+    base: u32,
+    cursor: usize,
+    buckets: Vec<Vec<S>>,
+    // the bucket at `cursor` is kept sorted ascending, pop takes from the back
+    cursor_sorted: bool,
+    in_window: usize,
+    overflow: BinaryHeap<S>,
+}
+
+impl<S: QueueItem> BucketQueue<S> {
+    pub fn new() -> BucketQueue<S> {
+        BucketQueue {
+            base: 0,
+            cursor: 0,
+            buckets: (0..WINDOW).map(|_| Vec::new()).collect(),
+            cursor_sorted: true,
+            in_window: 0,
+            overflow: BinaryHeap::new(),
+        }
+    }
+
+    fn slot(&self, key: u32) -> Option<usize> {
+        key.checked_sub(self.base).filter(|&offset| offset < WINDOW).map(|offset| offset as usize)
+    }
+
+    // moves the window to start at the smallest overflow key
+    fn rebase(&mut self) {
+        let Some(first) = self.overflow.peek() else { return };
+        self.base = first.key();
+        self.cursor = 0;
+        self.cursor_sorted = false;
+
+        while let Some(item) = self.overflow.peek() {
+            let Some(slot) = self.slot(item.key()) else { break };
+            let item = self.overflow.pop().unwrap();
+            self.buckets[slot].push(item);
+            self.in_window += 1;
+        }
+    }
+
+    pub fn push(&mut self, item: S) {
+        match self.slot(item.key()) {
+            Some(slot) => {
+                if slot < self.cursor {
+                    self.cursor = slot;
+                    self.cursor_sorted = false;
+                } else if slot == self.cursor {
+                    self.cursor_sorted = false;
+                }
+                self.buckets[slot].push(item);
+                self.in_window += 1;
+            }
+            None => self.overflow.push(item),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<S> {
+        if self.in_window == 0 {
+            if self.overflow.is_empty() {
+                return None;
+            }
+            self.rebase();
+        }
+
+        while self.buckets[self.cursor].is_empty() {
+            self.cursor += 1;
+            self.cursor_sorted = false;
+        }
+
+        // keys below the window live in the overflow and come first
+        let bucket_key = self.base + self.cursor as u32;
+        if self.overflow.peek().is_some_and(|item| item.key() < bucket_key) {
+            return self.overflow.pop();
+        }
+
+        let bucket = &mut self.buckets[self.cursor];
+        if !self.cursor_sorted {
+            bucket.sort_unstable();
+            self.cursor_sorted = true;
+        }
+        self.in_window -= 1;
+        bucket.pop()
+    }
+}
+
+impl<S: QueueItem> Default for BucketQueue<S> {
+    fn default() -> Self {
+        BucketQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift, enough to shuffle keys around
+    fn next(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    // Pushes and pops `states` in the same interleaving on both backends
+    fn pop_order<S: QueueItem>(backend: QueueBackend, states: &[S], pops_per_push: &[usize]) -> Vec<S> {
+        let mut queue = Queue::new(backend);
+        let mut popped = Vec::new();
+        for (&state, &pops) in states.iter().zip(pops_per_push) {
+            queue.push(state);
+            popped.extend((0..pops).map_while(|_| queue.pop()));
+        }
+        popped.extend(std::iter::from_fn(|| queue.pop()));
+        popped
+    }
+
+    #[test]
+    fn bucket_pops_like_the_heap() {
+        let mut seed = 0x9E37_79B9_7F4A_7C15;
+        // small steps, repeated keys, jumps past the window and keys below it
+        let states: Vec<State> = (0..5000).map(|i| {
+            let cost = match next(&mut seed) % 10 {
+                0 => (next(&mut seed) % 50) as u32,
+                1 => WINDOW * 3 + (next(&mut seed) % 100) as u32,
+                _ => 100 + (i as u32 % 700) + (next(&mut seed) % 20) as u32,
+            };
+            State { cost, index: (next(&mut seed) % 64) as usize }
+        }).collect();
+        let pops: Vec<usize> = (0..states.len()).map(|_| (next(&mut seed) % 3) as usize).collect();
+
+        let heap = pop_order(QueueBackend::BinaryHeap, &states, &pops);
+        let bucket = pop_order(QueueBackend::Bucket, &states, &pops);
+        assert_eq!(heap.len(), states.len());
+        assert!(heap == bucket, "pop order differs");
+    }
+
+    #[test]
+    fn grow_states_tie_break_the_same_way() {
+        let mut seed = 42;
+        let states: Vec<AutoGrowState> = (0..2000).map(|_| AutoGrowState {
+            sort_cost: (next(&mut seed) % 30) as u32,
+            true_cost: (next(&mut seed) % 5) as u32,
+            index: (next(&mut seed) % 8) as usize,
+            empire_id: (next(&mut seed) % 4) as u32,
+        }).collect();
+        let pops = vec![1; states.len()];
+
+        let heap = pop_order(QueueBackend::BinaryHeap, &states, &pops);
+        let bucket = pop_order(QueueBackend::Bucket, &states, &pops);
+        assert!(heap == bucket, "pop order differs");
+    }
+
+    #[test]
+    fn empty_queues_pop_nothing() {
+        for backend in [QueueBackend::BinaryHeap, QueueBackend::Bucket] {
+            let mut queue = Queue::<State>::new(backend);
+            assert!(queue.pop().is_none());
+            queue.push(State { cost: WINDOW * 10, index: 1 });
+            assert!(queue.pop().is_some_and(|s| s.cost == WINDOW * 10));
+            assert!(queue.pop().is_none());
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::World;
+use crate::grid::Grid;
+use crate::dirty::Layer;
+use crate::utlis::map_size;
+
+// Binary replay layout (all integers are LEB128 varints unless noted):
+//   magic "EREP", version byte
+//   width, height, keyframe_interval
+//   palette: count, then (empire_id, color as u32 LE) pairs
+//   initial owners: run count, then (run_length, owner) pairs
+//   ticks: count, then per tick a delta count followed by
+//          (zigzag index step from the previous delta, new owner) pairs
+const MAGIC: &[u8; 4] = b"EREP";
+const VERSION: u8 = 1;
+
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 32;
+
+// Keyframe bytes a decoded replay may rebuild per byte of input, so a file of empty ticks
+// can't make decode copy the whole grid on every one of them
+const KEYFRAME_BYTES_PER_INPUT_BYTE: u64 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OwnerDelta {
+    pub index: u32,
+    pub owner: u32,
+}
+
+struct Keyframe {
+    tick: u32,
+    owners: Vec<u32>,
+}
+
+/// Timeline of every ownership change, stored as a delta stream with
+/// periodic full copies of `owners` so any tick can be rebuilt quickly.
+///
+/// Tick 0 is the grid at the moment recording started; tick `t` is the grid
+/// after the `t`-th recorded operation (`add_empire`, `delete_empire`, `auto_grow`).
+pub struct Replay {
+    width: usize,
+    height: usize,
+    keyframe_interval: u32,
+    keyframes: Vec<Keyframe>,
+    deltas: Vec<OwnerDelta>,
+    // tick_ends[t - 1] is the end of tick t's slice in `deltas`
+    tick_ends: Vec<usize>,
+    palette: HashMap<u32, u32>,
+    recording: bool,
+
+    // The grid last produced by `seek`, and the tick it represents
+    view: Option<(u32, Grid<u32>)>,
+}
+
+impl Replay {
+    pub fn new(width: usize, height: usize, owners: Vec<u32>, keyframe_interval: u32) -> Replay {
+        Replay {
+            width,
+            height,
+            keyframe_interval: keyframe_interval.max(1),
+            keyframes: vec![Keyframe { tick: 0, owners }],
+            deltas: Vec::new(),
+            tick_ends: Vec::new(),
+            palette: HashMap::new(),
+            recording: true,
+            view: None,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool { self.recording }
+    pub fn set_recording(&mut self, recording: bool) { self.recording = recording; }
+
+    /// Number of recorded ticks (the last valid argument to `seek`)
+    pub fn tick_count(&self) -> u32 { self.tick_ends.len() as u32 }
+
+    pub fn palette(&self) -> &HashMap<u32, u32> { &self.palette }
+
+    pub fn view(&self) -> Option<&Grid<u32>> {
+        self.view.as_ref().map(|(_, owners)| owners)
+    }
+
+    pub fn view_tick(&self) -> Option<u32> {
+        self.view.as_ref().map(|(tick, _)| *tick)
+    }
+
+    pub fn clear_view(&mut self) { self.view = None; }
+
+    pub fn note_empire(&mut self, empire_id: u32, color: u32) {
+        if self.recording {
+            self.palette.insert(empire_id, color);
+        }
+    }
+
+    pub fn record(&mut self, index: usize, owner: u32) {
+        if self.recording {
+            self.deltas.push(OwnerDelta { index: index as u32, owner });
+        }
+    }
+
+    /// Closes the current tick. `snapshot` must return the live grid after the
+    /// tick's changes, it is only called when a keyframe is due.
+    pub fn commit_tick<F: FnOnce() -> Vec<u32>>(&mut self, snapshot: F) {
+        if !self.recording {
+            return;
+        }
+
+        self.tick_ends.push(self.deltas.len());
+
+        let tick = self.tick_count();
+        if tick.is_multiple_of(self.keyframe_interval) {
+            self.keyframes.push(Keyframe { tick, owners: snapshot() });
+        }
+    }
+
+    fn tick_deltas(&self, tick: u32) -> &[OwnerDelta] {
+        let t = tick as usize;
+        let start = if t <= 1 { 0 } else { self.tick_ends[t - 2] };
+        &self.deltas[start..self.tick_ends[t - 1]]
+    }
+
+    /// Rebuilds the owner grid at `tick` into the replay view.
+    /// Steps forward from the current view when possible, otherwise from the nearest keyframe.
+    pub fn seek(&mut self, tick: u32) -> bool {
+        if tick > self.tick_count() {
+            return false;
+        }
+
+        let key_pos = self.keyframes.partition_point(|k| k.tick <= tick) - 1;
+        let key_tick = self.keyframes[key_pos].tick;
+
+        let (mut current, mut owners) = match self.view.take() {
+            Some((view_tick, owners)) if view_tick <= tick && view_tick >= key_tick => (view_tick, owners),
+            _ => (key_tick, Grid::Flat(self.keyframes[key_pos].owners.clone())),
+        };
+
+        while current < tick {
+            current += 1;
+            for delta in self.tick_deltas(current) {
+                owners[delta.index as usize] = delta.owner;
+            }
+        }
+
+        self.view = Some((tick, owners));
+        true
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64 + self.deltas.len() * 3);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+
+        write_varint(&mut out, self.width as u64);
+        write_varint(&mut out, self.height as u64);
+        write_varint(&mut out, self.keyframe_interval as u64);
+
+        let mut palette: Vec<(&u32, &u32)> = self.palette.iter().collect();
+        palette.sort();
+        write_varint(&mut out, palette.len() as u64);
+        for (&id, &color) in palette {
+            write_varint(&mut out, id as u64);
+            out.extend_from_slice(&color.to_le_bytes());
+        }
+
+        // Initial grid as runs, maps are mostly long stretches of one owner
+        let initial = &self.keyframes[0].owners;
+        let mut runs: Vec<(u64, u32)> = Vec::new();
+        for &owner in initial {
+            match runs.last_mut() {
+                Some((len, last)) if *last == owner => *len += 1,
+                _ => runs.push((1, owner)),
+            }
+        }
+        write_varint(&mut out, runs.len() as u64);
+        for (len, owner) in runs {
+            write_varint(&mut out, len);
+            write_varint(&mut out, owner as u64);
+        }
+
+        write_varint(&mut out, self.tick_ends.len() as u64);
+        let mut prev_index: i64 = 0;
+        for tick in 1..=self.tick_count() {
+            let deltas = self.tick_deltas(tick);
+            write_varint(&mut out, deltas.len() as u64);
+            for delta in deltas {
+                let step = delta.index as i64 - prev_index;
+                write_varint(&mut out, ((step << 1) ^ (step >> 63)) as u64);
+                write_varint(&mut out, delta.owner as u64);
+                prev_index = delta.index as i64;
+            }
+        }
+
+        out
+    }
+
+    /// Parses a replay produced by `encode`. Keyframes are rebuilt while reading,
+    /// so the file only carries the initial grid and the delta stream.
+    pub fn decode(data: &[u8]) -> Result<Replay, String> {
+        if data.len() < 5 || &data[..4] != MAGIC {
+            return Err("Not a replay file".to_string());
+        }
+        if data[4] != VERSION {
+            return Err(format!("Unsupported replay version {}", data[4]));
+        }
+
+        let mut reader = Reader { data, pos: 5 };
+
+        let width = reader.varint()?;
+        let height = reader.varint()?;
+        let size = map_size(width, height).ok_or_else(|| format!("Invalid map size {}x{}", width, height))?;
+        let (width, height) = (width as usize, height as usize);
+        let keyframe_interval = reader.varint()?;
+
+        let mut palette = HashMap::new();
+        for _ in 0..reader.varint()? {
+            let id = reader.varint()? as u32;
+            let color = reader.u32_le()?;
+            palette.insert(id, color);
+        }
+
+        // grows with the runs actually read, a bogus run count can't reserve memory up front
+        let mut owners = Vec::new();
+        for _ in 0..reader.varint()? {
+            let len = reader.varint()?;
+            let owner = reader.varint()? as u32;
+            let end = (owners.len() as u64).checked_add(len)
+                .filter(|&end| end <= size as u64)
+                .ok_or("Initial grid is larger than the map")?;
+            owners.resize(end as usize, owner);
+        }
+        if owners.len() != size {
+            return Err("Initial grid is smaller than the map".to_string());
+        }
+
+        // keyframes are spaced out further when the file's interval would rebuild more of them
+        // than the input size allows
+        let tick_count = reader.varint()?;
+        let keyframe_bytes = tick_count.saturating_mul(size as u64 * 4);
+        let spacing = keyframe_bytes.div_ceil(data.len() as u64 * KEYFRAME_BYTES_PER_INPUT_BYTE);
+        let keyframe_interval = keyframe_interval.max(DEFAULT_KEYFRAME_INTERVAL as u64).max(spacing).min(u32::MAX as u64) as u32;
+
+        let mut replay = Replay::new(width, height, owners.clone(), keyframe_interval);
+        replay.palette = palette;
+
+        let mut prev_index: i64 = 0;
+        for _ in 0..tick_count {
+            for _ in 0..reader.varint()? {
+                let zigzag = reader.varint()?;
+                let step = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+                let index = prev_index + step;
+                if index < 0 || index as usize >= size {
+                    return Err(format!("Delta index {} is outside the map", index));
+                }
+                let owner = reader.varint()? as u32;
+
+                owners[index as usize] = owner;
+                replay.record(index as usize, owner);
+                prev_index = index;
+            }
+            replay.commit_tick(|| owners.clone());
+        }
+
+        if reader.pos != data.len() {
+            return Err("Trailing bytes after replay data".to_string());
+        }
+
+        replay.recording = false;
+        Ok(replay)
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) { (self.width, self.height) }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *self.data.get(self.pos).ok_or("Unexpected end of replay data")?;
+            self.pos += 1;
+            if shift >= 64 {
+                return Err("Varint is too long".to_string());
+            }
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn u32_le(&mut self) -> Result<u32, String> {
+        let bytes = self.data.get(self.pos..self.pos + 4).ok_or("Unexpected end of replay data")?;
+        self.pos += 4;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+
+// Recording and scrubbing the simulation timeline
+#[wasm_bindgen]
+impl World {
+    /// starts a fresh recording from the current owner grid
+    /// a keyframe_interval of 0 uses the default spacing
+    pub fn start_recording(&mut self, keyframe_interval: u32) {
+        let interval = if keyframe_interval == 0 { DEFAULT_KEYFRAME_INTERVAL } else { keyframe_interval };
+        let mut replay = Replay::new(self.width, self.height, self.owners.to_vec(), interval);
+
+        for empire in self.empires.values() {
+            replay.note_empire(empire.id, empire.color);
+        }
+
+        self.replay = Some(replay);
+    }
+
+    /// stops appending ticks, the recorded timeline stays available for seek/export
+    pub fn stop_recording(&mut self) {
+        if let Some(replay) = self.replay.as_mut() {
+            replay.set_recording(false);
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.replay.as_ref().is_some_and(|r| r.is_recording())
+    }
+
+    pub fn clear_replay(&mut self) {
+        self.seek_live();
+        self.replay = None;
+    }
+
+    pub fn replay_ticks(&self) -> u32 {
+        self.replay.as_ref().map_or(0, |r| r.tick_count())
+    }
+
+    /// rebuilds the owner grid at `tick`, render_ownership draws it until seek_live is called
+    pub fn seek(&mut self, tick: u32) -> bool {
+        let found = match self.replay.as_mut() {
+            Some(replay) => replay.seek(tick),
+            None => false,
+        };
+
+        if found {
+            self.mark_owner_layers();
+        }
+        found
+    }
+
+    /// tick currently shown by render_ownership, None when showing the live grid
+    pub fn seek_tick(&self) -> Option<u32> {
+        self.replay.as_ref().and_then(|r| r.view_tick())
+    }
+
+    /// goes back to rendering the live simulation
+    pub fn seek_live(&mut self) {
+        if let Some(replay) = self.replay.as_mut()
+            && replay.view_tick().is_some()
+        {
+            replay.clear_view();
+            self.mark_owner_layers();
+        }
+    }
+
+    pub fn export_replay(&self) -> Vec<u8> {
+        match self.replay.as_ref() {
+            Some(replay) => replay.encode(),
+            None => Vec::new(),
+        }
+    }
+
+    /// loads a shared replay for scrubbing, the map must have the same dimensions
+    pub fn import_replay(&mut self, data: &[u8]) -> bool {
+        let replay = match Replay::decode(data) {
+            Ok(replay) => replay,
+            Err(err) => {
+                console_log!("Replay import failed: {}", err);
+                return false;
+            }
+        };
+
+        if replay.dimensions() != (self.width, self.height) {
+            console_log!("Replay is for a {:?} map, this world is {}x{}", replay.dimensions(), self.width, self.height);
+            return false;
+        }
+
+        self.seek_live();
+        self.replay = Some(replay);
+        true
+    }
+}
+
+impl World {
+    // switching between the live grid and a replay view redraws everything that depends on owners
+    fn mark_owner_layers(&mut self) {
+        self.dirty.mark_layer(Layer::Ownership);
+        self.dirty.mark_layer(Layer::Borders);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4x3 grid where tick t hands tile t to empire t
+    fn recorded(ticks: u32, keyframe_interval: u32) -> Replay {
+        let mut replay = Replay::new(4, 3, vec![0; 12], keyframe_interval);
+        replay.note_empire(1, 0xFF0000FF);
+        let mut owners = vec![0; 12];
+        for tick in 1..=ticks {
+            let index = tick as usize % 12;
+            owners[index] = tick;
+            replay.record(index, tick);
+            replay.commit_tick(|| owners.clone());
+        }
+        replay
+    }
+
+    fn grid_at(replay: &mut Replay, tick: u32) -> Vec<u32> {
+        assert!(replay.seek(tick));
+        replay.view().unwrap().to_vec()
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let mut original = recorded(20, 4);
+        let mut decoded = Replay::decode(&original.encode()).unwrap();
+
+        assert_eq!(decoded.dimensions(), (4, 3));
+        assert_eq!(decoded.tick_count(), 20);
+        assert_eq!(decoded.palette().get(&1), Some(&0xFF0000FF));
+        for tick in [0, 1, 7, 20, 3] {
+            assert_eq!(grid_at(&mut decoded, tick), grid_at(&mut original, tick), "tick {}", tick);
+        }
+        assert!(!decoded.seek(21));
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let data = recorded(5, 4).encode();
+        for len in [0, 3, 5, 8, data.len() - 1] {
+            assert!(Replay::decode(&data[..len]).is_err(), "{} of {} bytes decoded", len, data.len());
+        }
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(Replay::decode(&trailing).is_err());
+    }
+
+    #[test]
+    fn decoded_keyframes_are_bounded_by_the_input() {
+        // 1000x1000 map of one run, interval 1 and 100000 empty ticks of 1 byte each
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        for value in [1000, 1000, 1, 0, 1, 1_000_000, 0, 100_000] {
+            write_varint(&mut data, value);
+        }
+        data.extend(std::iter::repeat_n(0, 100_000));
+
+        let replay = Replay::decode(&data).unwrap();
+        assert_eq!(replay.tick_count(), 100_000);
+        let keyframe_bytes: usize = replay.keyframes.iter().map(|k| k.owners.len() * 4).sum();
+        assert!(keyframe_bytes as u64 <= 4_000_000 + data.len() as u64 * KEYFRAME_BYTES_PER_INPUT_BYTE, "{} keyframe bytes", keyframe_bytes);
+    }
+}
@@ -0,0 +1,124 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use crate::utlis::MAX_TILES;
+
+// Map rows may be run-length encoded: a decimal count before a character repeats it,
+// so `120W3P` is 120 water tiles followed by 3 plains. Terrain and resource characters
+// are never digits, so plain rows decode to themselves and both forms can be mixed.
+
+/// Non-empty rows of a map string, trimmed and expanded. A run is expanded at most one
+/// tile past `width` (the first row's width when None), enough for the caller to see the
+/// mismatch without allocating a bogus run, and no more than MAX_TILES tiles in total.
+pub fn rows(map_data: &str, width: Option<usize>) -> Vec<Cow<'_, str>> {
+    let mut limit = width.map(|w| w + 1);
+    let mut budget = MAX_TILES;
+    let mut rows = Vec::new();
+
+    for line in map_data.lines().filter(|l| !l.is_empty()) {
+        if budget == 0 {
+            console_log!("Map data is larger than {} tiles, the rest is dropped", MAX_TILES);
+            break;
+        }
+        let row = expand(line.trim(), limit.unwrap_or(MAX_TILES).min(budget));
+        budget = budget.saturating_sub(row.len());
+        limit.get_or_insert(row.len() + 1);
+        rows.push(row);
+    }
+
+    rows
+}
+
+// Expands the runs of `row`, stopping once it is `max` tiles long
+fn expand(row: &str, max: usize) -> Cow<'_, str> {
+    if !row.bytes().any(|b| b.is_ascii_digit()) {
+        return Cow::Borrowed(row);
+    }
+
+    let mut output = String::with_capacity((row.len() * 8).min(max));
+    let mut count: Option<usize> = None;
+    for c in row.chars() {
+        match c.to_digit(10) {
+            Some(digit) => {
+                count = count.unwrap_or(0).checked_mul(10).and_then(|n| n.checked_add(digit as usize));
+                if count.is_none() {
+                    console_log!("Run length in `{}` is too large, row cut short", row);
+                    break;
+                }
+            }
+            None => {
+                let run = count.take().unwrap_or(1);
+                let room = max.saturating_sub(output.len());
+                output.extend(std::iter::repeat_n(c, run.min(room)));
+                if run > room {
+                    console_log!("Row `{}` is longer than {} tiles, row cut short", row, max);
+                    break;
+                }
+            }
+        }
+    }
+    if count.is_some() {
+        console_log!("Row `{}` ends with a run length but no character", row);
+    }
+
+    Cow::Owned(output)
+}
+
+/// Appends one row, runs of more than one tile written as `<count><char>`
+pub fn push_row(output: &mut String, row: impl Iterator<Item = char>) {
+    let mut row = row.peekable();
+    while let Some(c) = row.next() {
+        let mut run = 1;
+        while row.next_if_eq(&c).is_some() {
+            run += 1;
+        }
+        if run > 1 {
+            let _ = write!(output, "{}", run);
+        }
+        output.push(c);
+    }
+    output.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(row: &str) -> String {
+        let mut output = String::new();
+        push_row(&mut output, row.chars());
+        output
+    }
+
+    #[test]
+    fn push_row_round_trips_through_rows() {
+        let map = ["WWWWWWWWWWWWPPPM", "M", "g..........w", "RRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRR"];
+        let text: String = map.iter().map(|row| encoded(row)).collect();
+        assert_eq!(text, "12W3PM\nM\ng10.w\n45R\n");
+
+        let decoded = rows(&text, Some(64));
+        assert_eq!(decoded, map);
+    }
+
+    #[test]
+    fn plain_rows_are_borrowed() {
+        let decoded = rows("  WPM \n\nPPP\n2PW\n", None);
+        assert!(matches!(decoded[0], Cow::Borrowed("WPM")));
+        assert!(matches!(decoded[1], Cow::Borrowed("PPP")));
+        assert_eq!(decoded[2], "PPW");
+    }
+
+    #[test]
+    fn runs_stop_one_tile_past_the_width() {
+        assert_eq!(rows("4000000000W\nP1000000000000M", Some(3)), ["WWWW", "PMMM"]);
+        // the first row sets the width when none is given
+        assert_eq!(rows("WWP\n99999999P\n2W", None), ["WWP", "PPPP", "WW"]);
+    }
+
+    #[test]
+    fn oversized_and_dangling_counts_cut_the_row() {
+        assert_eq!(rows("2W99999999999999999999999P", Some(10)), ["WW"]);
+        assert_eq!(rows("3W12", Some(10)), ["WWW"]);
+        assert_eq!(rows("0WP", Some(10)), ["P"]);
+    }
+}
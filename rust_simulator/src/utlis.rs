@@ -11,3 +11,14 @@ pub const INTI_COSTS: [u32; 8] = [
     20,  // 6: Forest
     100,  // 7: Ice
 ];
+
+/// Largest map read from a file or replay or made by a size change, in tiles (8192 x 8192)
+pub const MAX_TILES: usize = 1 << 26;
+
+/// width * height when the map is not empty and within MAX_TILES. Takes the sizes as read
+/// from a file, before they are narrowed to usize.
+pub fn map_size(width: u64, height: u64) -> Option<usize> {
+    width.checked_mul(height)
+        .filter(|&size| size > 0 && size <= MAX_TILES as u64)
+        .map(|size| size as usize)
+}
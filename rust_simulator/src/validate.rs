@@ -0,0 +1,225 @@
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::dirty::Layer;
+use crate::filters::most_common;
+use crate::{Resource, Terrain, World};
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    /// `?` or a character the parser did not know, fixed by taking the neighbours' terrain
+    UnknownTile = 0,
+    /// resource on Water or Unknown, fixed by removing it
+    ResourceOnUnliveable = 1,
+    /// land tile with only watery tiles around it, fixed by sinking it unless an empire owns it
+    IsolatedIsland = 2,
+    /// River tiles that never touch Water, reported once per river at its first tile, not fixed
+    RiverWithoutOutlet = 3,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub x: usize,
+    pub y: usize,
+    message: String,
+}
+
+#[wasm_bindgen]
+impl Issue {
+    pub fn message(&self) -> String { self.message.clone() }
+}
+
+fn is_land(terrain: Terrain) -> bool {
+    terrain.is_liveable() && !terrain.is_watery()
+}
+
+impl World {
+    fn neighbour_indices(&self, index: usize) -> impl Iterator<Item = usize> {
+        let (x, y, width) = (index % self.width, index / self.width, self.width);
+        [
+            (x > 0).then(|| index - 1),
+            (x + 1 < width).then(|| index + 1),
+            (y > 0).then(|| index - width),
+            (y + 1 < self.height).then(|| index + width),
+        ].into_iter().flatten()
+    }
+
+    fn clear_resource(&mut self, index: usize) {
+        self.resources.set(index, Resource::None);
+        if let Some(pixel) = self.resource_buffer.get_mut(index) {
+            *pixel = Resource::None.get_color();
+        }
+        let (x, y) = (index % self.width, index / self.width);
+        self.dirty.mark(Layer::Resources, x, y, x, y, 0);
+    }
+
+    fn issue(&self, kind: IssueKind, index: usize, message: String) -> Issue {
+        Issue { kind, x: index % self.width, y: index / self.width, message }
+    }
+
+    // Rivers as 4-connected River tiles, the ones that never touch Water
+    fn rivers_without_outlet(&self) -> Vec<Issue> {
+        let mut seen = vec![false; self.width * self.height];
+        let mut issues = Vec::new();
+
+        for start in 0..seen.len() {
+            if seen[start] || self.tiles[start] != Terrain::River { continue; }
+
+            let (mut length, mut outlet) = (0, false);
+            let mut stack = vec![start];
+            seen[start] = true;
+            while let Some(index) = stack.pop() {
+                length += 1;
+                for next in self.neighbour_indices(index) {
+                    match self.tiles[next] {
+                        Terrain::Water => outlet = true,
+                        Terrain::River if !seen[next] => {
+                            seen[next] = true;
+                            stack.push(next);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if !outlet {
+                issues.push(self.issue(IssueKind::RiverWithoutOutlet, start, format!("river of {} tiles does not reach water", length)));
+            }
+        }
+
+        issues
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Problems to look at before a simulation, sorted by position
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues: Vec<Issue> = (0..self.width * self.height)
+            .into_par_iter()
+            .flat_map_iter(|index| {
+                let terrain = self.tiles[index];
+                let resource = self.resources[index];
+                let mut found = Vec::new();
+
+                if terrain == Terrain::Unknown {
+                    found.push(self.issue(IssueKind::UnknownTile, index, "unknown terrain".to_string()));
+                }
+                if !terrain.is_liveable() && resource != Resource::None {
+                    found.push(self.issue(IssueKind::ResourceOnUnliveable, index, format!("{:?} on {:?}", resource, terrain)));
+                }
+                if is_land(terrain) && self.neighbour_indices(index).all(|n| self.tiles[n].is_watery()) {
+                    found.push(self.issue(IssueKind::IsolatedIsland, index, format!("one-tile {:?} island", terrain)));
+                }
+                found
+            })
+            .collect();
+
+        issues.extend(self.rivers_without_outlet());
+        issues.sort_by_key(|issue| (issue.y, issue.x, issue.kind as u8));
+        issues
+    }
+
+    /// Fixes the issues with a mechanical fix (see IssueKind) and returns them
+    pub fn auto_fix(&mut self) -> Vec<Issue> {
+        let mut fixed = Vec::new();
+
+        for issue in self.validate() {
+            let index = issue.y * self.width + issue.x;
+            match issue.kind {
+                IssueKind::UnknownTile => {
+                    let known = self.neighbour_indices(index).map(|n| self.tiles[n]).filter(|&t| t != Terrain::Unknown);
+                    let terrain = most_common(known).map_or(Terrain::Water, |(t, _)| t);
+                    self.draw_terrain(&[index], terrain);
+                }
+                // comes after the UnknownTile fix of the same tile, which may have made it land
+                IssueKind::ResourceOnUnliveable => {
+                    if !self.tiles[index].is_liveable() {
+                        self.clear_resource(index);
+                    }
+                }
+                IssueKind::IsolatedIsland if self.owners[index] == 0 => {
+                    self.draw_terrain(&[index], Terrain::Water);
+                    self.clear_resource(index);
+                }
+                _ => continue,
+            }
+            fixed.push(issue);
+        }
+
+        fixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One-tile islands at (1, 1) and (5, 1), the second owned by empire 1, unknown tiles at
+    // (2, 2) in the water and (4, 3) on land, and a river at the bottom that never meets water
+    const TERRAIN: &str = "\
+WWWWWWW
+WPWWWMW
+WW?PPWW
+WRWP?PP
+WWWPRRP
+";
+    const RESOURCES: &str = "\
+f......
+.g.....
+..s....
+....c..
+...w...
+";
+
+    fn world() -> World {
+        let mut world = World::new(TERRAIN, Some(RESOURCES.to_string()));
+        // size 0 so the empire holds only its capital
+        assert!(world.add_empire(5, 1, 1, 0xFF0000FF, 0, vec![9999, 25, 10, 15, 80, 60, 20, 100]));
+        world
+    }
+
+    fn summary(issues: &[Issue]) -> Vec<(IssueKind, usize, usize)> {
+        issues.iter().map(|issue| (issue.kind, issue.x, issue.y)).collect()
+    }
+
+    #[test]
+    fn validate_lists_every_issue_by_position() {
+        let issues = world().validate();
+        assert_eq!(summary(&issues), [
+            (IssueKind::ResourceOnUnliveable, 0, 0),
+            (IssueKind::IsolatedIsland, 1, 1),
+            (IssueKind::IsolatedIsland, 5, 1),
+            (IssueKind::UnknownTile, 2, 2),
+            (IssueKind::ResourceOnUnliveable, 2, 2),
+            (IssueKind::UnknownTile, 4, 3),
+            (IssueKind::ResourceOnUnliveable, 4, 3),
+            (IssueKind::RiverWithoutOutlet, 4, 4),
+        ]);
+        assert_eq!(issues[7].message(), "river of 2 tiles does not reach water");
+    }
+
+    #[test]
+    fn auto_fix_leaves_owned_islands_and_rivers_alone() {
+        let mut world = world();
+        let fixed = world.auto_fix();
+        assert_eq!(summary(&fixed), [
+            (IssueKind::ResourceOnUnliveable, 0, 0),
+            (IssueKind::IsolatedIsland, 1, 1),
+            (IssueKind::UnknownTile, 2, 2),
+            (IssueKind::ResourceOnUnliveable, 2, 2),
+            (IssueKind::UnknownTile, 4, 3),
+            (IssueKind::ResourceOnUnliveable, 4, 3),
+        ]);
+
+        // the unknown tiles took their neighbours' terrain, the one that became land kept its coal
+        assert_eq!(world.export_map_to_string(false), "WWWWWWW\nWWWWWMW\nWWWPPWW\nWRWPPPP\nWWWPRRP\n");
+        assert_eq!(world.export_resource_to_string(false), ".......\n.......\n.......\n....c..\n...w...\n");
+
+        assert_eq!(summary(&world.validate()), [(IssueKind::IsolatedIsland, 5, 1), (IssueKind::RiverWithoutOutlet, 4, 4)]);
+        assert!(summary(&world.auto_fix()).is_empty());
+    }
+}
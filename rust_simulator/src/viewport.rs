@@ -0,0 +1,109 @@
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::{World, owner_color};
+use crate::composite::blend_over;
+use crate::utlis::{MAX_TILES, map_size};
+
+// Empire colors over terrain in the viewport, same as the 0.75 opacity of the overlay canvases
+const VIEWPORT_OWNERSHIP_OPACITY: u32 = 191;
+
+// Tiles averaged per pixel along each axis when zoomed out, wider boxes are sampled evenly
+const MAX_BOX_SAMPLES: u64 = 16;
+
+// Up to MAX_BOX_SAMPLES tiles spread evenly over [min, max)
+fn box_samples(min: i64, max: i64) -> impl Iterator<Item = i64> {
+    let span = max.saturating_sub(min).max(1) as u64;
+    let stride = span.div_ceil(MAX_BOX_SAMPLES);
+    (0..span.min(MAX_BOX_SAMPLES)).map(move |k| min.saturating_add((k * stride) as i64))
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Renders the world window [x0, x1) x [y0, y1) (in tiles, fractions allowed) into an
+    /// out_w x out_h buffer, composing terrain, ownership and resources.
+    /// Zoomed in (a pixel covers at most one tile) samples the nearest tile,
+    /// zoomed out averages the tiles under the pixel (at most 16 x 16 of them, evenly spread).
+    /// Outside the map is transparent. The buffer holds at most MAX_TILES pixels.
+    pub fn render_viewport(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, out_w: usize, out_h: usize) -> Result<(), String> {
+        if out_w == 0 || out_h == 0 {
+            self.viewport_buffer.clear();
+            return Ok(());
+        }
+        let size = map_size(out_w as u64, out_h as u64)
+            .ok_or_else(|| format!("a {}x{} viewport is larger than {} pixels", out_w, out_h, MAX_TILES))?;
+        self.viewport_buffer.resize(size, 0);
+
+        // colors come straight from the grids, so a chunked world never needs full-size layer buffers
+        let width = self.width as i64;
+        let height = self.height as i64;
+        let tiles = &self.tiles;
+        let resources = &self.resources;
+        let empires = &self.empires;
+        let (owners, palette) = World::displayed_owners(&self.owners, &self.replay);
+
+        let tile_color = |x: i64, y: i64| -> u32 {
+            if x < 0 || y < 0 || x >= width || y >= height {
+                return 0x00000000;
+            }
+            let index = (y * width + x) as usize;
+            let mut color = tiles[index].get_color();
+            if owners[index] != 0 {
+                color = blend_over(color, owner_color(empires, palette, owners[index]), VIEWPORT_OWNERSHIP_OPACITY);
+            }
+            blend_over(color, resources[index].get_color(), 255)
+        };
+
+        let step_x = (x1 - x0) / out_w as f32;
+        let step_y = (y1 - y0) / out_h as f32;
+        let zoomed_in = step_x.abs() <= 1.0 && step_y.abs() <= 1.0;
+
+        self.viewport_buffer
+            .par_chunks_mut(out_w)
+            .enumerate()
+            .for_each(|(py, row)| {
+                let top = y0 + py as f32 * step_y;
+                for (px, pixel) in row.iter_mut().enumerate() {
+                    let left = x0 + px as f32 * step_x;
+
+                    if zoomed_in {
+                        let x = (left + step_x * 0.5).floor() as i64;
+                        let y = (top + step_y * 0.5).floor() as i64;
+                        *pixel = tile_color(x, y);
+                        continue;
+                    }
+
+                    // Box filter, averaged with premultiplied alpha so transparent tiles don't darken the result
+                    let (min_x, max_x) = (left.min(left + step_x).floor() as i64, left.max(left + step_x).ceil() as i64);
+                    let (min_y, max_y) = (top.min(top + step_y).floor() as i64, top.max(top + step_y).ceil() as i64);
+
+                    let mut sums = [0u64; 4];
+                    let mut count = 0u64;
+                    for y in box_samples(min_y, max_y) {
+                        for x in box_samples(min_x, max_x) {
+                            let color = tile_color(x, y);
+                            let a = (color >> 24) as u64;
+                            sums[0] += (color & 0xFF) as u64 * a;
+                            sums[1] += ((color >> 8) & 0xFF) as u64 * a;
+                            sums[2] += ((color >> 16) & 0xFF) as u64 * a;
+                            sums[3] += a;
+                            count += 1;
+                        }
+                    }
+
+                    *pixel = match sums[3] {
+                        0 => 0x00000000,
+                        alpha_sum => {
+                            let r = sums[0] / alpha_sum;
+                            let g = sums[1] / alpha_sum;
+                            let b = sums[2] / alpha_sum;
+                            let a = alpha_sum / count;
+                            ((a << 24) | (b << 16) | (g << 8) | r) as u32
+                        }
+                    };
+                }
+            });
+
+        Ok(())
+    }
+}